@@ -3,31 +3,315 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+#define OBJC_SYNC_SUCCESS 0
+
+#define OBJC_SYNC_NOT_OWNING_THREAD_ERROR -1
+
+#define BLOCK_NEEDS_FREE (1 << 24)
+
+#define BLOCK_HAS_COPY_DISPOSE (1 << 25)
+
+#define BLOCK_IS_GLOBAL (1 << 28)
+
+/**
+ * The block returns a structure through memory, whose address it's passed
+ * before anything else.
+ */
+#define BLOCK_USE_STRET (1 << 29)
+
+#define BLOCK_FIELD_IS_OBJECT 3
+
+#define BLOCK_FIELD_IS_BLOCK 7
+
+#define BLOCK_FIELD_IS_BYREF 8
+
+#define BLOCK_FIELD_IS_WEAK 16
+
+#define STRIPE_COUNT 64
+
+/**
+ * Number of low pointer bits holding a small object's tag.
+ */
+#define SMALL_OBJECT_BITS 3
+
+#define SMALL_OBJECT_MASK ((1 << SMALL_OBJECT_BITS) - 1)
+
 typedef struct Option_objc_imp Option_objc_imp;
 
 typedef struct Property Property;
 
+typedef struct Protocol Protocol;
+
+/**
+ * A bundle of methods, protocols and properties to be attached to an existing
+ * class. Categories are consumed when they are attached; if the class they
+ * name has not been registered yet, they are held by the [Context] until it is.
+ *
+ * [Context]: super::context::Context
+ */
+typedef struct objc_category objc_category;
+
 typedef struct objc_ivar objc_ivar;
 
 typedef struct objc_method objc_method;
 
 typedef struct objc_selector objc_selector;
 
+typedef ClassKey Receiver;
+
+typedef Receiver *id;
+
+typedef struct objc_selector *SEL;
+
+typedef uintptr_t objc_AssociationPolicy;
+
+typedef struct Option_objc_imp IMP;
+
+typedef struct objc_category *Category;
+
+/**
+ * A single attribute of a property, such as `T` (type) or `V` (backing ivar).
+ */
+typedef struct objc_property_attribute_t {
+  const char *name;
+  const char *value;
+} objc_property_attribute_t;
+
 typedef struct objc_method *Method;
 
 typedef Repr<ClassData> objc_class;
 
 typedef objc_class *Class;
 
-typedef struct objc_selector *SEL;
-
 typedef struct objc_ivar *Ivar;
 
-typedef struct Option_objc_imp IMP;
+typedef void (*objc_uncaught_exception_handler)(id);
 
-typedef ClassKey Receiver;
+/**
+ * The C-facing description of a method a protocol declares.
+ */
+typedef struct objc_method_description {
+  SEL name;
+  const char *types;
+} objc_method_description;
 
-typedef Receiver *id;
+#define OBJC_ASSOCIATION_ASSIGN 0
+
+#define OBJC_ASSOCIATION_RETAIN_NONATOMIC 1
+
+#define OBJC_ASSOCIATION_COPY_NONATOMIC 3
+
+#define OBJC_ASSOCIATION_RETAIN 769
+
+#define OBJC_ASSOCIATION_COPY 771
+
+extern const uintptr_t _NSConcreteStackBlock[32];
+
+extern const uintptr_t _NSConcreteGlobalBlock[32];
+
+extern const uintptr_t _NSConcreteMallocBlock[32];
+
+id objc_getProperty(id self_, SEL _cmd, ptrdiff_t offset, bool atomic);
+
+/**
+ * [should_copy] is 0 to store [new_value] as is, 1 to store its `-copy` and 2
+ * to store its `-mutableCopy`.
+ */
+void objc_setProperty(id self_,
+                      SEL _cmd,
+                      ptrdiff_t offset,
+                      id new_value,
+                      bool atomic,
+                      int8_t should_copy);
+
+void objc_setProperty_atomic(id self_, SEL _cmd, id new_value, ptrdiff_t offset);
+
+void objc_setProperty_nonatomic(id self_, SEL _cmd, id new_value, ptrdiff_t offset);
+
+void objc_setProperty_atomic_copy(id self_, SEL _cmd, id new_value, ptrdiff_t offset);
+
+void objc_setProperty_nonatomic_copy(id self_, SEL _cmd, id new_value, ptrdiff_t offset);
+
+/**
+ * Copies a non-object property value of [size] bytes. Atomic copies hold the
+ * locks for both [src] and [dest] so neither side is observed half-written.
+ */
+void objc_copyStruct(void *dest, const void *src, ptrdiff_t size, bool atomic, bool _has_strong);
+
+id objc_retain(id obj);
+
+void objc_release(id obj);
+
+id objc_autorelease(id obj);
+
+size_t objc_retainCount(id obj);
+
+/**
+ * Retains [obj] without checking for a custom `-retain`, so that classes
+ * implementing one can defer to the runtime.
+ */
+id _objc_rootRetain(id obj);
+
+/**
+ * Releases [obj] without checking for a custom `-release`, sending it
+ * `-dealloc` if this was the last reference.
+ */
+void _objc_rootRelease(id obj);
+
+size_t _objc_rootRetainCount(id obj);
+
+/**
+ * Stores [obj] into the strong variable at [location], retaining [obj] and
+ * releasing the previous value.
+ */
+void objc_storeStrong(id *location, id obj);
+
+id objc_retainAutorelease(id obj);
+
+/**
+ * Autoreleases [obj] on behalf of a function returning it, unless the caller
+ * takes it straight back with [objc_retainAutoreleasedReturnValue] or
+ * [objc_unsafeClaimAutoreleasedReturnValue].
+ */
+id objc_autoreleaseReturnValue(id obj);
+
+id objc_retainAutoreleaseReturnValue(id obj);
+
+/**
+ * Retains a value returned from [objc_autoreleaseReturnValue]. If it was
+ * handed over directly, the callee's reference is reused instead.
+ */
+id objc_retainAutoreleasedReturnValue(id obj);
+
+/**
+ * Accepts a value returned from [objc_autoreleaseReturnValue] without taking
+ * a reference to it.
+ */
+id objc_unsafeClaimAutoreleasedReturnValue(id obj);
+
+/**
+ * Copies [block] to the heap if it's still on the stack, or retains it
+ * otherwise.
+ */
+id objc_retainBlock(id block);
+
+/**
+ * Initializes a fresh weak variable at [location] to point to [obj].
+ */
+id objc_initWeak(id *location, id obj);
+
+/**
+ * Stores [obj] into the weak variable at [location]. Returns the value
+ * stored, which is nil if [obj] is being deallocated.
+ */
+id objc_storeWeak(id *location, id obj);
+
+id objc_loadWeakRetained(id *location);
+
+id objc_loadWeak(id *location);
+
+/**
+ * Initializes the weak variable at [to] with the value of the one at [from].
+ */
+void objc_copyWeak(id *to, id *from);
+
+/**
+ * Like [objc_copyWeak], but leaves [from] nil.
+ */
+void objc_moveWeak(id *to, id *from);
+
+void objc_destroyWeak(id *location);
+
+void *objc_autoreleasePoolPush(void);
+
+void objc_autoreleasePoolPop(void *pool);
+
+void objc_setAssociatedObject(id object, const void *key, id value, objc_AssociationPolicy policy);
+
+id objc_getAssociatedObject(id object, const void *key);
+
+/**
+ * Removes all of [object]'s associations, including ones set by code other
+ * than the caller's.
+ */
+void objc_removeAssociatedObjects(id object);
+
+/**
+ * Copies [block] to the heap if it's on the stack, or retains it if it's
+ * already there. Returns the copy, which must be released with
+ * [_Block_release].
+ */
+void *_Block_copy(const void *block);
+
+/**
+ * Releases a block returned by [_Block_copy].
+ */
+void _Block_release(const void *block);
+
+/**
+ * Called by compiler-generated copy helpers to copy the captured variable
+ * [object] into [dest], retaining or copying it as [flags] says.
+ */
+void _Block_object_assign(void *dest, const void *object, int flags);
+
+/**
+ * Called by compiler-generated dispose helpers to release the captured
+ * variable [object].
+ */
+void _Block_object_dispose(const void *object, int flags);
+
+/**
+ * Returns an implementation that calls [block] with the receiver followed by
+ * the method's arguments, leaving out the selector. [block] is copied, and
+ * stays alive until the implementation is passed to [imp_removeBlock].
+ */
+IMP imp_implementationWithBlock(id block);
+
+/**
+ * The block called by [imp], if it was created by
+ * [imp_implementationWithBlock].
+ */
+id imp_getBlock(IMP imp);
+
+/**
+ * Frees [imp], which must have been created by [imp_implementationWithBlock]
+ * and must no longer be called, and releases its block. Returns false if
+ * [imp] wasn't created from a block.
+ */
+bool imp_removeBlock(IMP imp);
+
+/**
+ * Creates a new, empty category on the class named [class_name]. The category
+ * is owned by the caller until it is passed to [objc_attachCategory].
+ */
+Category objc_allocateCategory(const char *class_name, const char *name);
+
+bool category_addMethod(Category cat, SEL name, IMP imp, const char *types);
+
+bool category_addClassMethod(Category cat, SEL name, IMP imp, const char *types);
+
+bool category_addProtocol(Category cat, struct Protocol *protocol);
+
+/**
+ * Adds a property to [cat], which the class gets when the category is
+ * attached, ahead of the class' own properties. Returns false if [cat]
+ * already has a property named [name].
+ */
+bool category_addProperty(Category cat,
+                          const char *name,
+                          const struct objc_property_attribute_t *attributes,
+                          unsigned int attribute_count);
+
+const char *category_getName(Category cat);
+
+/**
+ * Attaches [cat] to its class, taking ownership of it. If the class has not
+ * been registered yet, attachment is deferred until [objc_registerClassPair]
+ * is called for it.
+ *
+ * [objc_registerClassPair]: super::objc_registerClassPair
+ */
+void objc_attachCategory(Category cat);
 
 Method class_getClassMethod(Class cls, SEL name);
 
@@ -49,21 +333,94 @@ bool class_addIvar(Class cls, const char *name, size_t size, uint8_t alignment,
 
 struct objc_ivar *_Nonnull *class_copyIvarList(Class cls, unsigned int *out_count);
 
-const uint8_t *class_getIvarLayout(Class _cls);
-
-void class_setIvarLayout(Class _cls, const uint8_t *_layout);
-
-const uint8_t *class_weakGetIvarLayout(Class _cls);
-
-void class_weakSetIvarLayout(Class _cls, const uint8_t *_layout);
+/**
+ * Returns a bitmap of the words of an instance of [cls] holding strong object
+ * pointers, or null if there are none.
+ */
+const uint8_t *class_getIvarLayout(Class cls);
+
+/**
+ * Marks the ivars covered by [layout] as strong. Only allowed between
+ * [objc_allocateClassPair] and [objc_registerClassPair].
+ *
+ * [objc_allocateClassPair]: super::objc_allocateClassPair
+ * [objc_registerClassPair]: super::objc_registerClassPair
+ */
+void class_setIvarLayout(Class cls, const uint8_t *layout);
+
+/**
+ * Like [class_getIvarLayout], but for weak object pointers.
+ */
+const uint8_t *class_weakGetIvarLayout(Class cls);
+
+/**
+ * Like [class_setIvarLayout], but marks the ivars as weak.
+ */
+void class_weakSetIvarLayout(Class cls, const uint8_t *layout);
 
 struct Property *class_getProperty(Class cls, const char *name);
 
 struct Property *_Nonnull *class_copyPropertyList(Class cls, unsigned int *out_count);
 
+/**
+ * Returns false if [cls] already has a property named [name].
+ */
+bool class_addProperty(Class cls,
+                       const char *name,
+                       const struct objc_property_attribute_t *attributes,
+                       unsigned int attribute_count);
+
+/**
+ * Replaces the attributes of [cls]'s property named [name], or adds the
+ * property if [cls] doesn't have one.
+ */
+void class_replaceProperty(Class cls,
+                           const char *name,
+                           const struct objc_property_attribute_t *attributes,
+                           unsigned int attribute_count);
+
 bool class_addMethod(Class cls, SEL name, IMP imp, const char *types);
 
-id class_createInstance(Class cls, size_t _extra_bytes);
+/**
+ * Adds [protocol] to the protocols [cls] adopts. Returns false if [cls]
+ * already conforms to it, including through a superclass.
+ */
+bool class_addProtocol(Class cls, struct Protocol *protocol);
+
+bool class_conformsToProtocol(Class cls, struct Protocol *protocol);
+
+id class_createInstance(Class cls, size_t extra_bytes);
+
+/**
+ * Enters the catch clause for [exception], returning the thrown object.
+ */
+id objc_begin_catch(void *exception);
+
+/**
+ * Leaves the innermost catch clause, destroying its exception unless it was
+ * rethrown.
+ */
+void objc_end_catch(void);
+
+/**
+ * Sets the function called with Objective-C exceptions that nothing catches,
+ * before the process aborts. Returns the previous handler.
+ */
+objc_uncaught_exception_handler objc_setUncaughtExceptionHandler(objc_uncaught_exception_handler handler);
+
+/**
+ * The personality routine of Objective-C frames with `@catch` or `@finally`
+ * blocks, which tells the unwinder what each frame does with an exception.
+ *
+ * # Safety
+ *
+ * Must only be called by the unwinder.
+ */
+int __gnu_objc_personality_v0(int version,
+                              int actions,
+                              uint64_t _exception_class,
+                              void *exception,
+                              void *context);
 
 Class objc_allocateClassPair(Class superclass, const char *name, size_t extra_bytes);
 
@@ -71,22 +428,173 @@ id objc_getClass(const char *name);
 
 void objc_registerClassPair(Class cls);
 
+/**
+ * Makes [cls] the class of small objects whose low [SMALL_OBJECT_BITS] bits
+ * equal [tag]. Returns false if [tag] is zero or out of range, or if another
+ * class already uses it.
+ *
+ * [SMALL_OBJECT_BITS]: crate::runtime::small_object::SMALL_OBJECT_BITS
+ */
+bool objc_registerSmallObjectClass_np(Class cls, uintptr_t tag);
+
 id objc_getMetaClass(const char *name);
 
+/**
+ * Reads an object ivar. Weak ivars are loaded with [objc_loadWeak].
+ */
 id object_getIvar(id obj, Ivar ivar);
 
+/**
+ * Turns [bytes], which must be zero-filled, suitably aligned and at least
+ * [class_getInstanceSize] bytes long, into an instance of [cls].
+ *
+ * [class_getInstanceSize]: super::class_getInstanceSize
+ */
+id objc_constructInstance(Class cls, void *bytes);
+
+/**
+ * Runs [obj]'s `.cxx_destruct` methods from its class up, releases its
+ * strong ivars, unregisters its weak ivars and releases its associated
+ * objects, without freeing it. Returns [obj].
+ */
+void *objc_destructInstance(id obj);
+
+/**
+ * Destructs and frees [obj], which must have been created by
+ * [class_createInstance]. Always returns nil.
+ *
+ * [class_createInstance]: super::class_createInstance
+ */
+id object_dispose(id obj);
+
+/**
+ * Returns a pointer to the storage following [obj]'s ivars, which holds the
+ * extra bytes requested when it was created.
+ */
+void *object_getIndexedIvars(id obj);
+
 Class object_getClass(id obj);
 
+/**
+ * Sets the class of [obj] to [cls], returning its previous class. The class
+ * of a small object is fixed by its tag and a block's by how it was
+ * allocated, so nil is returned for those and their class is left alone.
+ */
+Class object_setClass(id obj, Class cls);
+
+const char *object_getClassName(id obj);
+
+/**
+ * Creates a new instance of [obj]'s class with [extra_bytes] of indexed ivars
+ * and copies [obj]'s ivars into it, retaining what strong ivars point to and
+ * registering weak ones with their objects. Indexed ivars are not copied.
+ * Small objects are values, so they are returned as they are. Blocks must be
+ * copied with [_Block_copy] instead, so nil is returned for those.
+ *
+ * [_Block_copy]: super::_Block_copy
+ */
+id object_copy(id obj, size_t extra_bytes);
+
 ptrdiff_t ivar_getOffset(Ivar ivar);
 
+/**
+ * Stores [value] into an object ivar. Ivars of unknown ownership are treated
+ * as `__unsafe_unretained`.
+ */
 void object_setIvar(id obj, Ivar ivar, id value);
 
+/**
+ * Like [object_setIvar], but ivars of unknown ownership are treated as
+ * `__strong`.
+ */
+void object_setIvarWithStrongDefault(id obj, Ivar ivar, id value);
+
 Ivar object_getInstanceVariable(id obj, const char *name, void **out_value);
 
 Ivar object_setInstanceVariable(id obj, const char *name, void *value);
 
+const char *property_getName(struct Property *property);
+
+const char *property_getAttributes(struct Property *property);
+
+/**
+ * The returned attributes point into [property], which must outlive them.
+ * The caller owns the returned array itself.
+ */
+struct objc_property_attribute_t *property_copyAttributeList(struct Property *property,
+                                                             unsigned int *out_count);
+
+/**
+ * Returns a copy of the value of the attribute named [attribute_name], which
+ * the caller must release with `free`.
+ */
+char *property_copyAttributeValue(struct Property *property, const char *attribute_name);
+
+struct Protocol *objc_allocateProtocol(const char *name);
+
+void objc_registerProtocol(struct Protocol *proto);
+
+struct Protocol *objc_getProtocol(const char *name);
+
+struct Protocol *_Nonnull *objc_copyProtocolList(unsigned int *out_count);
+
+const char *protocol_getName(struct Protocol *proto);
+
+bool protocol_isEqual(struct Protocol *proto, struct Protocol *other);
+
+/**
+ * Whether [proto] is [other] or inherits from it, directly or indirectly.
+ */
+bool protocol_conformsToProtocol(struct Protocol *proto, struct Protocol *other);
+
+void protocol_addMethodDescription(struct Protocol *proto,
+                                   SEL name,
+                                   const char *types,
+                                   bool is_required_method,
+                                   bool is_instance_method);
+
+/**
+ * Makes [proto] inherit from [addition]. [proto] must still be under
+ * construction, and [addition] must already be registered.
+ */
+void protocol_addProtocol(struct Protocol *proto, struct Protocol *addition);
+
+/**
+ * Adds a property to [proto], which must still be under construction.
+ * Optional properties are not supported and are ignored.
+ */
+void protocol_addProperty(struct Protocol *proto,
+                          const char *name,
+                          const struct objc_property_attribute_t *attributes,
+                          unsigned int attribute_count,
+                          bool is_required_property,
+                          bool is_instance_property);
+
+struct objc_method_description *protocol_copyMethodDescriptionList(struct Protocol *proto,
+                                                                   bool is_required_method,
+                                                                   bool is_instance_method,
+                                                                   unsigned int *out_count);
+
 const char *sel_getName(SEL sel);
 
 bool sel_isEqual(SEL lhs, SEL rhs);
 
 SEL sel_registerName(const char *name);
+
+/**
+ * Takes the recursive lock associated with [obj], blocking until it's
+ * available. Does nothing for nil.
+ */
+int objc_sync_enter(id obj);
+
+/**
+ * Releases the lock taken by [objc_sync_enter]. Fails with
+ * [OBJC_SYNC_NOT_OWNING_THREAD_ERROR] if the current thread doesn't hold it.
+ */
+int objc_sync_exit(id obj);
+
+extern void __clear_cache(char *start, char *end);
+
+void objc_exception_throw(id object) __attribute__((noreturn));
+
+void objc_exception_rethrow(void *exception) __attribute__((noreturn));
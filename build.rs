@@ -1,4 +1,4 @@
-use std::env;
+use std::{env, path::Path, process::Command};
 
 use cbindgen::Config;
 
@@ -11,4 +11,37 @@ fn main() {
         .generate()
         .expect("Unable to generate bindings")
         .write_to_file("bindings.h");
+
+    build_test_fixtures(Path::new(&crate_dir));
+}
+
+/// Compiles the C++ and Objective-C frames the exception tests throw through,
+/// which only the integration tests link against.
+fn build_test_fixtures(crate_dir: &Path) {
+    let out_dir = Path::new(&env::var("OUT_DIR").unwrap()).to_owned();
+    let fixtures = crate_dir.join("tests/fixtures");
+
+    let mut sources = vec![("CXX", "c++", "exceptions.cc")];
+    // Hand-written, since there's no Objective-C compiler to emit them
+    if env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "x86_64" {
+        sources.push(("CC", "cc", "objc_catch_x86_64.S"));
+    }
+
+    for (variable, default, source) in sources {
+        let compiler = env::var(variable).unwrap_or_else(|_| default.to_owned());
+        let source = fixtures.join(source);
+        let object = out_dir
+            .join(source.file_stem().unwrap())
+            .with_extension("o");
+        let status = Command::new(compiler)
+            .arg("-c")
+            .arg(&source)
+            .arg("-o")
+            .arg(&object)
+            .status()
+            .expect("Unable to run the C compiler");
+        assert!(status.success(), "Unable to compile {}", source.display());
+        println!("cargo:rustc-link-arg-tests={}", object.display());
+    }
+    println!("cargo:rustc-link-arg-tests=-lstdc++");
 }
@@ -0,0 +1,135 @@
+//! Entry points used by compiler-synthesized property accessors. [offset] is
+//! always an ivar offset, as returned by [ivar_getOffset].
+//!
+//! [ivar_getOffset]: super::ivar_getOffset
+
+use super::names::{COPY, MUTABLE_COPY};
+use super::objc::send_message;
+use super::{objc_autorelease, objc_release, objc_retain};
+use crate::runtime::{
+    id,
+    lock::{SpinLock, StripedMap, STRIPE_COUNT},
+    object::objc_object,
+    SEL,
+};
+use libc::ptrdiff_t;
+use std::ffi::{c_void, CStr};
+
+static PROPERTY_LOCKS: StripedMap<SpinLock> =
+    StripedMap::new([const { SpinLock::new() }; STRIPE_COUNT]);
+
+static STRUCT_LOCKS: StripedMap<SpinLock> =
+    StripedMap::new([const { SpinLock::new() }; STRIPE_COUNT]);
+
+#[no_mangle]
+pub extern "C" fn objc_getProperty(self_: id, _cmd: SEL, offset: ptrdiff_t, atomic: bool) -> id {
+    let slot = unsafe { objc_object::ivar_ptr(self_?.cast(), offset as usize) } as *mut id;
+
+    if !atomic {
+        return unsafe { slot.read() };
+    }
+
+    // Atomic getters must not hand out a value another thread may be about to
+    // release, so it's retained while the lock is held.
+    let value = {
+        let _guard = PROPERTY_LOCKS.for_address(slot).lock();
+        objc_retain(unsafe { slot.read() })
+    };
+    objc_autorelease(value)
+}
+
+fn set_property(self_: id, offset: ptrdiff_t, new_value: id, atomic: bool, copy: Option<&CStr>) {
+    let Some(self_) = self_ else {
+        return;
+    };
+
+    // Either way, we now own a reference to the new value
+    let new_value = match copy {
+        Some(copy) => send_message(new_value, copy),
+        None => objc_retain(new_value),
+    };
+
+    let slot = unsafe { objc_object::ivar_ptr(self_.cast(), offset as usize) } as *mut id;
+
+    let old_value = if atomic {
+        let _guard = PROPERTY_LOCKS.for_address(slot).lock();
+        unsafe { slot.replace(new_value) }
+    } else {
+        unsafe { slot.replace(new_value) }
+    };
+
+    objc_release(old_value);
+}
+
+/// [should_copy] is 0 to store [new_value] as is, 1 to store its `-copy` and 2
+/// to store its `-mutableCopy`.
+#[no_mangle]
+pub extern "C" fn objc_setProperty(
+    self_: id,
+    _cmd: SEL,
+    offset: ptrdiff_t,
+    new_value: id,
+    atomic: bool,
+    should_copy: i8,
+) {
+    let copy = match should_copy {
+        0 => None,
+        2 => Some(MUTABLE_COPY),
+        _ => Some(COPY),
+    };
+    set_property(self_, offset, new_value, atomic, copy)
+}
+
+#[no_mangle]
+pub extern "C" fn objc_setProperty_atomic(self_: id, _cmd: SEL, new_value: id, offset: ptrdiff_t) {
+    set_property(self_, offset, new_value, true, None)
+}
+
+#[no_mangle]
+pub extern "C" fn objc_setProperty_nonatomic(
+    self_: id,
+    _cmd: SEL,
+    new_value: id,
+    offset: ptrdiff_t,
+) {
+    set_property(self_, offset, new_value, false, None)
+}
+
+#[no_mangle]
+pub extern "C" fn objc_setProperty_atomic_copy(
+    self_: id,
+    _cmd: SEL,
+    new_value: id,
+    offset: ptrdiff_t,
+) {
+    set_property(self_, offset, new_value, true, Some(COPY))
+}
+
+#[no_mangle]
+pub extern "C" fn objc_setProperty_nonatomic_copy(
+    self_: id,
+    _cmd: SEL,
+    new_value: id,
+    offset: ptrdiff_t,
+) {
+    set_property(self_, offset, new_value, false, Some(COPY))
+}
+
+/// Copies a non-object property value of [size] bytes. Atomic copies hold the
+/// locks for both [src] and [dest] so neither side is observed half-written.
+#[no_mangle]
+pub extern "C" fn objc_copyStruct(
+    dest: *mut c_void,
+    src: *const c_void,
+    size: ptrdiff_t,
+    atomic: bool,
+    _has_strong: bool,
+) {
+    let _guards = atomic.then(|| {
+        SpinLock::lock_two(
+            STRUCT_LOCKS.for_address(src),
+            STRUCT_LOCKS.for_address(dest),
+        )
+    });
+    unsafe { std::ptr::copy(src.cast::<u8>(), dest.cast::<u8>(), size as usize) };
+}
@@ -0,0 +1,267 @@
+// These are entry points for C, where the pointer arguments are the caller's
+// responsibility just as with Apple's runtime.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use super::global_context::CONTEXT;
+use super::names::{AUTORELEASE, DEALLOC, RELEASE, RETAIN, RETAIN_COUNT};
+use super::{_Block_copy, objc_msg_lookup, object_dispose, sel_registerName};
+use crate::runtime::{autorelease, id, message::Receiver, refcount, small_object, weak};
+use std::{
+    ffi::{c_void, CStr},
+    ptr::NonNull,
+};
+
+/// How an object's retain count is managed.
+enum RetainRelease {
+    /// Class objects live forever and small objects aren't in memory at all,
+    /// so neither is counted.
+    Ignored,
+    /// The runtime manages the count itself.
+    Fast,
+    /// The object's class implements some of its own reference counting
+    /// methods, so they have to be sent as messages. Methods it doesn't
+    /// implement still fall back to the runtime.
+    Custom,
+}
+
+fn retain_release(obj: NonNull<Receiver>) -> RetainRelease {
+    if small_object::tag(obj).is_some() {
+        return RetainRelease::Ignored;
+    }
+
+    let context = CONTEXT.read().expect("poisoned rwlock");
+    let Some(class_key) = context.class_of(obj) else {
+        return RetainRelease::Ignored;
+    };
+    match context.classes.get(class_key) {
+        None => RetainRelease::Ignored,
+        Some(class) if class.is_metaclass() => RetainRelease::Ignored,
+        Some(_) if context.has_custom_rr(class_key) => RetainRelease::Custom,
+        Some(_) => RetainRelease::Fast,
+    }
+}
+
+/// Sends [obj] the message [name] if it responds to it, or calls [fallback]
+/// otherwise.
+fn send_or_else(obj: id, name: &CStr, fallback: impl FnOnce() -> id) -> id {
+    let sel = unsafe { sel_registerName(name.as_ptr()) };
+    match objc_msg_lookup(obj, sel) {
+        Some(imp) => unsafe { imp(obj, sel) },
+        None => fallback(),
+    }
+}
+
+/// Sends [obj] `-dealloc`, or disposes of it directly if its class doesn't
+/// implement it.
+fn dealloc(obj: NonNull<Receiver>) {
+    send_or_else(Some(obj), DEALLOC, || object_dispose(Some(obj)));
+}
+
+#[no_mangle]
+pub extern "C" fn objc_retain(obj: id) -> id {
+    let receiver = obj?;
+    match retain_release(receiver) {
+        RetainRelease::Ignored => obj,
+        RetainRelease::Fast => _objc_rootRetain(obj),
+        RetainRelease::Custom => send_or_else(obj, RETAIN, || _objc_rootRetain(obj)),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn objc_release(obj: id) {
+    let Some(receiver) = obj else {
+        return;
+    };
+    match retain_release(receiver) {
+        RetainRelease::Ignored => (),
+        RetainRelease::Fast => _objc_rootRelease(obj),
+        RetainRelease::Custom => {
+            send_or_else(obj, RELEASE, || {
+                _objc_rootRelease(obj);
+                None
+            });
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn objc_autorelease(obj: id) -> id {
+    let receiver = obj?;
+    match retain_release(receiver) {
+        RetainRelease::Ignored => obj,
+        RetainRelease::Fast => {
+            autorelease::autorelease(receiver);
+            obj
+        }
+        RetainRelease::Custom => send_or_else(obj, AUTORELEASE, || obj),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn objc_retainCount(obj: id) -> libc::size_t {
+    let Some(receiver) = obj else {
+        return 0;
+    };
+    match retain_release(receiver) {
+        RetainRelease::Ignored => usize::MAX,
+        RetainRelease::Fast => _objc_rootRetainCount(obj),
+        RetainRelease::Custom => {
+            let sel = unsafe { sel_registerName(RETAIN_COUNT.as_ptr()) };
+            match objc_msg_lookup(obj, sel) {
+                // `-retainCount` returns an integer, not an object
+                Some(imp) => unsafe { imp(obj, sel) }.map_or(0, |count| count.addr().get()),
+                None => _objc_rootRetainCount(obj),
+            }
+        }
+    }
+}
+
+/// Retains [obj] without checking for a custom `-retain`, so that classes
+/// implementing one can defer to the runtime.
+#[no_mangle]
+pub extern "C" fn _objc_rootRetain(obj: id) -> id {
+    if let Some(obj) = small_object::in_memory(obj) {
+        refcount::retain(obj);
+    }
+    obj
+}
+
+/// Releases [obj] without checking for a custom `-release`, sending it
+/// `-dealloc` if this was the last reference.
+#[no_mangle]
+pub extern "C" fn _objc_rootRelease(obj: id) {
+    if let Some(obj) = small_object::in_memory(obj) {
+        if refcount::release(obj) {
+            dealloc(obj);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _objc_rootRetainCount(obj: id) -> libc::size_t {
+    match obj {
+        Some(obj) if small_object::tag(obj).is_some() => usize::MAX,
+        obj => obj.map_or(0, refcount::retain_count),
+    }
+}
+
+/// Stores [obj] into the strong variable at [location], retaining [obj] and
+/// releasing the previous value.
+#[no_mangle]
+pub extern "C" fn objc_storeStrong(location: *mut id, obj: id) {
+    let previous = unsafe { location.read() };
+    if previous == obj {
+        return;
+    }
+    objc_retain(obj);
+    unsafe { location.write(obj) };
+    objc_release(previous);
+}
+
+#[no_mangle]
+pub extern "C" fn objc_retainAutorelease(obj: id) -> id {
+    objc_autorelease(objc_retain(obj))
+}
+
+/// Autoreleases [obj] on behalf of a function returning it, unless the caller
+/// takes it straight back with [objc_retainAutoreleasedReturnValue] or
+/// [objc_unsafeClaimAutoreleasedReturnValue].
+#[no_mangle]
+pub extern "C" fn objc_autoreleaseReturnValue(obj: id) -> id {
+    let receiver = obj?;
+    match retain_release(receiver) {
+        RetainRelease::Fast => {
+            autorelease::autorelease_return_value(receiver);
+            obj
+        }
+        _ => objc_autorelease(obj),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn objc_retainAutoreleaseReturnValue(obj: id) -> id {
+    objc_autoreleaseReturnValue(objc_retain(obj))
+}
+
+/// Retains a value returned from [objc_autoreleaseReturnValue]. If it was
+/// handed over directly, the callee's reference is reused instead.
+#[no_mangle]
+pub extern "C" fn objc_retainAutoreleasedReturnValue(obj: id) -> id {
+    if autorelease::claim_return_value(obj?) {
+        return obj;
+    }
+    autorelease::flush_return_value();
+    objc_retain(obj)
+}
+
+/// Accepts a value returned from [objc_autoreleaseReturnValue] without taking
+/// a reference to it.
+#[no_mangle]
+pub extern "C" fn objc_unsafeClaimAutoreleasedReturnValue(obj: id) -> id {
+    if autorelease::claim_return_value(obj?) {
+        objc_release(obj);
+    }
+    obj
+}
+
+/// Copies [block] to the heap if it's still on the stack, or retains it
+/// otherwise.
+#[no_mangle]
+pub extern "C" fn objc_retainBlock(block: id) -> id {
+    let block = block.map_or(std::ptr::null(), |block| block.as_ptr().cast());
+    NonNull::new(_Block_copy(block).cast())
+}
+
+/// Initializes a fresh weak variable at [location] to point to [obj].
+#[no_mangle]
+pub extern "C" fn objc_initWeak(location: *mut id, obj: id) -> id {
+    unsafe { location.write(None) };
+    weak::store(location, obj)
+}
+
+/// Stores [obj] into the weak variable at [location]. Returns the value
+/// stored, which is nil if [obj] is being deallocated.
+#[no_mangle]
+pub extern "C" fn objc_storeWeak(location: *mut id, obj: id) -> id {
+    weak::store(location, obj)
+}
+
+#[no_mangle]
+pub extern "C" fn objc_loadWeakRetained(location: *mut id) -> id {
+    weak::load_retained(location)
+}
+
+#[no_mangle]
+pub extern "C" fn objc_loadWeak(location: *mut id) -> id {
+    objc_autorelease(weak::load_retained(location))
+}
+
+/// Initializes the weak variable at [to] with the value of the one at [from].
+#[no_mangle]
+pub extern "C" fn objc_copyWeak(to: *mut id, from: *mut id) {
+    let obj = weak::load_retained(from);
+    objc_initWeak(to, obj);
+    objc_release(obj);
+}
+
+/// Like [objc_copyWeak], but leaves [from] nil.
+#[no_mangle]
+pub extern "C" fn objc_moveWeak(to: *mut id, from: *mut id) {
+    objc_copyWeak(to, from);
+    weak::store(from, None);
+}
+
+#[no_mangle]
+pub extern "C" fn objc_destroyWeak(location: *mut id) {
+    weak::store(location, None);
+}
+
+#[no_mangle]
+pub extern "C" fn objc_autoreleasePoolPush() -> *mut c_void {
+    autorelease::push()
+}
+
+#[no_mangle]
+pub extern "C" fn objc_autoreleasePoolPop(pool: *mut c_void) {
+    autorelease::pop(pool)
+}
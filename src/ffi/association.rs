@@ -0,0 +1,144 @@
+//! Associated objects: extra values attached to an object under an arbitrary
+//! pointer-sized key, released when the object is destroyed.
+
+use super::{names::COPY, objc_autorelease, objc_release, objc_retain, send_message};
+use crate::runtime::id;
+use std::{collections::BTreeMap, ffi::c_void, sync::Mutex};
+
+#[allow(non_camel_case_types)]
+pub type objc_AssociationPolicy = usize;
+
+pub const OBJC_ASSOCIATION_ASSIGN: objc_AssociationPolicy = 0;
+pub const OBJC_ASSOCIATION_RETAIN_NONATOMIC: objc_AssociationPolicy = 1;
+pub const OBJC_ASSOCIATION_COPY_NONATOMIC: objc_AssociationPolicy = 3;
+pub const OBJC_ASSOCIATION_RETAIN: objc_AssociationPolicy = 0o1401;
+pub const OBJC_ASSOCIATION_COPY: objc_AssociationPolicy = 0o1403;
+
+// The policies above are combinations of what the setter does with the new
+// value and what the getter does with the stored one.
+const SETTER_MASK: objc_AssociationPolicy = 0xff;
+const SETTER_RETAIN: objc_AssociationPolicy = 1;
+const SETTER_COPY: objc_AssociationPolicy = 3;
+const GETTER_RETAIN: objc_AssociationPolicy = 1 << 8;
+const GETTER_AUTORELEASE: objc_AssociationPolicy = 2 << 8;
+
+struct Association {
+    policy: objc_AssociationPolicy,
+    value: id,
+}
+
+// Associated values are only touched under the lock, and retained ones are
+// kept alive by the association itself.
+unsafe impl Send for Association {}
+
+impl Association {
+    /// Releases the value if the association owns it.
+    fn release(self) {
+        if matches!(self.policy & SETTER_MASK, SETTER_RETAIN | SETTER_COPY) {
+            objc_release(self.value);
+        }
+    }
+}
+
+/// Associations by object address, then by key.
+static ASSOCIATIONS: Mutex<BTreeMap<usize, BTreeMap<usize, Association>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Associates [value] with [obj] under [key], replacing (and releasing) any
+/// previous value. A nil [value] removes the association.
+fn set(obj: id, key: *const c_void, value: id, policy: objc_AssociationPolicy) {
+    let Some(obj) = obj else {
+        return;
+    };
+
+    // Retain or copy the new value before taking the lock: either may send
+    // messages that want to touch associations themselves.
+    let value = match policy & SETTER_MASK {
+        SETTER_RETAIN => objc_retain(value),
+        SETTER_COPY => value.and_then(|value| send_message(Some(value), COPY)),
+        _ => value,
+    };
+
+    let old = {
+        let mut associations = ASSOCIATIONS.lock().expect("poisoned mutex");
+        match value {
+            Some(_) => associations
+                .entry(obj.as_ptr().addr())
+                .or_default()
+                .insert(key.addr(), Association { policy, value }),
+            None => {
+                let object_associations = associations.get_mut(&obj.as_ptr().addr());
+                let old = object_associations.and_then(|assocs| assocs.remove(&key.addr()));
+                associations.retain(|_, assocs| !assocs.is_empty());
+                old
+            }
+        }
+    };
+
+    // Releasing may deallocate the old value, which removes its own
+    // associations, so the lock must not be held.
+    if let Some(old) = old {
+        old.release();
+    }
+}
+
+/// Returns the value associated with [obj] under [key], or nil.
+fn get(obj: id, key: *const c_void) -> id {
+    let obj = obj?;
+
+    let (policy, value) = {
+        let associations = ASSOCIATIONS.lock().expect("poisoned mutex");
+        let association = associations.get(&obj.as_ptr().addr())?.get(&key.addr())?;
+
+        // Atomic associations retain under the lock, so another thread
+        // replacing the value can't free it before we return it.
+        if association.policy & GETTER_RETAIN != 0 {
+            objc_retain(association.value);
+        }
+        (association.policy, association.value)
+    };
+
+    if policy & GETTER_AUTORELEASE != 0 {
+        objc_autorelease(value)
+    } else {
+        value
+    }
+}
+
+/// Removes and releases all of [obj]'s associations.
+pub(crate) fn remove_all(obj: id) {
+    let Some(obj) = obj else {
+        return;
+    };
+
+    let removed = ASSOCIATIONS
+        .lock()
+        .expect("poisoned mutex")
+        .remove(&obj.as_ptr().addr());
+
+    for association in removed.into_iter().flat_map(BTreeMap::into_values) {
+        association.release();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn objc_setAssociatedObject(
+    object: id,
+    key: *const c_void,
+    value: id,
+    policy: objc_AssociationPolicy,
+) {
+    set(object, key, value, policy)
+}
+
+#[no_mangle]
+pub extern "C" fn objc_getAssociatedObject(object: id, key: *const c_void) -> id {
+    get(object, key)
+}
+
+/// Removes all of [object]'s associations, including ones set by code other
+/// than the caller's.
+#[no_mangle]
+pub extern "C" fn objc_removeAssociatedObjects(object: id) {
+    remove_all(object)
+}
@@ -0,0 +1,83 @@
+use crate::runtime::{
+    block::{self, Block_layout, BLOCK_USE_STRET},
+    id, trampoline, IMP,
+};
+use std::{
+    ffi::{c_int, c_void},
+    ptr::NonNull,
+    sync::atomic::Ordering,
+};
+
+/// The isa of blocks on the stack. Only its address matters.
+#[no_mangle]
+pub static _NSConcreteStackBlock: [usize; 32] = [0; 32];
+/// The isa of blocks that capture nothing, which the compiler emits as
+/// constants.
+#[no_mangle]
+pub static _NSConcreteGlobalBlock: [usize; 32] = [0; 32];
+/// The isa of blocks copied to the heap.
+#[no_mangle]
+pub static _NSConcreteMallocBlock: [usize; 32] = [0; 32];
+
+/// Copies [block] to the heap if it's on the stack, or retains it if it's
+/// already there. Returns the copy, which must be released with
+/// [_Block_release].
+#[no_mangle]
+pub extern "C" fn _Block_copy(block: *const c_void) -> *mut c_void {
+    unsafe { block::copy(block.cast::<Block_layout>().cast_mut()) }.cast()
+}
+
+/// Releases a block returned by [_Block_copy].
+#[no_mangle]
+pub extern "C" fn _Block_release(block: *const c_void) {
+    unsafe { block::release(block.cast::<Block_layout>().cast_mut()) }
+}
+
+/// Called by compiler-generated copy helpers to copy the captured variable
+/// [object] into [dest], retaining or copying it as [flags] says.
+#[no_mangle]
+pub extern "C" fn _Block_object_assign(dest: *mut c_void, object: *const c_void, flags: c_int) {
+    unsafe { block::assign_object(dest.cast(), object.cast_mut(), flags) }
+}
+
+/// Called by compiler-generated dispose helpers to release the captured
+/// variable [object].
+#[no_mangle]
+pub extern "C" fn _Block_object_dispose(object: *const c_void, flags: c_int) {
+    unsafe { block::dispose_object(object.cast_mut(), flags) }
+}
+
+/// Returns an implementation that calls [block] with the receiver followed by
+/// the method's arguments, leaving out the selector. [block] is copied, and
+/// stays alive until the implementation is passed to [imp_removeBlock].
+#[no_mangle]
+pub extern "C" fn imp_implementationWithBlock(block: id) -> IMP {
+    let block = _Block_copy(block?.as_ptr().cast()).cast::<Block_layout>();
+    let flags = unsafe { &*block }.flags.load(Ordering::Relaxed);
+    let imp = trampoline::allocate(block, flags & BLOCK_USE_STRET != 0);
+    if imp.is_none() {
+        _Block_release(block.cast());
+    }
+    imp
+}
+
+/// The block called by [imp], if it was created by
+/// [imp_implementationWithBlock].
+#[no_mangle]
+pub extern "C" fn imp_getBlock(imp: IMP) -> id {
+    NonNull::new(trampoline::block(imp?)?.cast())
+}
+
+/// Frees [imp], which must have been created by [imp_implementationWithBlock]
+/// and must no longer be called, and releases its block. Returns false if
+/// [imp] wasn't created from a block.
+#[no_mangle]
+pub extern "C" fn imp_removeBlock(imp: IMP) -> bool {
+    match imp.and_then(trampoline::remove) {
+        Some(block) => {
+            _Block_release(block.cast());
+            true
+        }
+        None => false,
+    }
+}
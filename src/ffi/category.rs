@@ -0,0 +1,137 @@
+// Categories are built from C, which passes names and method lists as raw
+// pointers that it vouches for.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use super::global_context::CONTEXT;
+use super::property::new_property;
+use crate::runtime::{
+    category::{objc_category, Category},
+    method::{objc_method, IMP},
+    property::objc_property_attribute_t,
+    protocol::Protocol,
+    SEL,
+};
+use std::{
+    ffi::{c_char, c_uint, CStr},
+    ptr::NonNull,
+};
+
+/// Creates a new, empty category on the class named [class_name]. The category
+/// is owned by the caller until it is passed to [objc_attachCategory].
+#[no_mangle]
+pub extern "C" fn objc_allocateCategory(
+    class_name: *const c_char,
+    name: *const c_char,
+) -> Category {
+    let class_name = unsafe { CStr::from_ptr(class_name) }.to_owned();
+    let name = unsafe { CStr::from_ptr(name) }.to_owned();
+    NonNull::new(Box::into_raw(Box::new(objc_category::new(
+        class_name, name,
+    ))))
+}
+
+fn add_method(methods: &mut Vec<objc_method>, name: SEL, imp: IMP, types: *const c_char) -> bool {
+    let x: Option<()> = try {
+        let name = unsafe { name?.as_ref() };
+        let imp = imp?;
+        let types = unsafe { CStr::from_ptr(types) }
+            .to_owned()
+            .into_string()
+            .expect("invalid utf8");
+
+        methods.push(objc_method::new(imp, name, types));
+    };
+    x.is_some()
+}
+
+#[no_mangle]
+pub extern "C" fn category_addMethod(
+    cat: Category,
+    name: SEL,
+    imp: IMP,
+    types: *const c_char,
+) -> bool {
+    match cat {
+        None => false,
+        Some(mut cat) => add_method(
+            &mut unsafe { cat.as_mut() }.instance_methods,
+            name,
+            imp,
+            types,
+        ),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn category_addClassMethod(
+    cat: Category,
+    name: SEL,
+    imp: IMP,
+    types: *const c_char,
+) -> bool {
+    match cat {
+        None => false,
+        Some(mut cat) => add_method(&mut unsafe { cat.as_mut() }.class_methods, name, imp, types),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn category_addProtocol(cat: Category, protocol: Option<NonNull<Protocol>>) -> bool {
+    let x: Option<()> = try {
+        let cat = unsafe { cat?.as_mut() };
+        let protocol = unsafe { protocol?.as_ref() };
+        cat.protocols.push(protocol.index);
+    };
+    x.is_some()
+}
+
+/// Adds a property to [cat], which the class gets when the category is
+/// attached, ahead of the class' own properties. Returns false if [cat]
+/// already has a property named [name].
+#[no_mangle]
+pub extern "C" fn category_addProperty(
+    cat: Category,
+    name: *const c_char,
+    attributes: *const objc_property_attribute_t,
+    attribute_count: c_uint,
+) -> bool {
+    let x: Option<()> = try {
+        let cat = unsafe { cat?.as_mut() };
+        let name = unsafe { CStr::from_ptr(name) };
+        if cat
+            .properties
+            .iter()
+            .any(|property| property.name.as_c_str() == name)
+        {
+            None?
+        }
+
+        cat.properties
+            .push(new_property(name, attributes, attribute_count));
+    };
+    x.is_some()
+}
+
+#[no_mangle]
+pub extern "C" fn category_getName(cat: Category) -> *const c_char {
+    match cat {
+        None => std::ptr::null(),
+        Some(cat) => unsafe { cat.as_ref() }.name.as_ptr(),
+    }
+}
+
+/// Attaches [cat] to its class, taking ownership of it. If the class has not
+/// been registered yet, attachment is deferred until [objc_registerClassPair]
+/// is called for it.
+///
+/// [objc_registerClassPair]: super::objc_registerClassPair
+#[no_mangle]
+pub extern "C" fn objc_attachCategory(cat: Category) {
+    if let Some(cat) = cat {
+        let cat = unsafe { Box::from_raw(cat.as_ptr()) };
+        CONTEXT
+            .write()
+            .expect("poisoned rwlock")
+            .attach_category(*cat);
+    }
+}
@@ -1,10 +1,17 @@
+// Classes are introspected and modified from C, which hands over names, ivar
+// layouts and attribute lists as raw pointers it answers for.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
 use super::empty_string::EMPTY_STRING;
 use super::global_context::CONTEXT;
+use super::object::construct_instance;
+use super::property::new_property;
 use crate::runtime::{
     id,
-    ivar::{objc_ivar, Ivar},
-    method::{objc_method, Method, IMP},
-    property::Property,
+    ivar::{decode_layout, objc_ivar, Ivar, Ownership},
+    method::{Method, IMP},
+    object::objc_object,
+    property::{objc_property_attribute_t, Property},
+    protocol::Protocol,
     Class, SEL,
 };
 use std::{
@@ -180,38 +187,81 @@ pub extern "C" fn class_copyIvarList(
     )
 }
 
+/// Returns the layout bitmap for [ownership] ivars, or null if there are none.
+fn get_ivar_layout(cls: Class, ownership: Ownership) -> *const u8 {
+    let Some(mut cls) = cls else {
+        return std::ptr::null();
+    };
+
+    match unsafe { cls.as_mut() }.layout_bitmap(ownership) {
+        [0] => std::ptr::null(),
+        layout => layout.as_ptr(),
+    }
+}
+
+/// Applies a layout bitmap for [ownership] ivars to [cls]. Ignored once the
+/// class has been registered. A null layout marks no ivars.
+fn set_ivar_layout(cls: Class, layout: *const u8, ownership: Ownership) {
+    let Some(mut cls) = cls else {
+        return;
+    };
+
+    let registered = {
+        let cls = unsafe { cls.as_ref() };
+        let context = CONTEXT.read().expect("poisoned rwlock");
+        context.registered_classes.get(&cls.name) == Some(&cls.index)
+    };
+    if registered {
+        return;
+    }
+
+    let words = if layout.is_null() {
+        Default::default()
+    } else {
+        unsafe { decode_layout(layout) }
+    };
+    unsafe { cls.as_mut() }.set_layout_bitmap(ownership, &words);
+}
+
+/// Returns a bitmap of the words of an instance of [cls] holding strong object
+/// pointers, or null if there are none.
 #[no_mangle]
-pub extern "C" fn class_getIvarLayout(_cls: Class) -> *const u8 {
-    unimplemented!("no garbage collection support yet")
+pub extern "C" fn class_getIvarLayout(cls: Class) -> *const u8 {
+    get_ivar_layout(cls, Ownership::Strong)
 }
 
+/// Marks the ivars covered by [layout] as strong. Only allowed between
+/// [objc_allocateClassPair] and [objc_registerClassPair].
+///
+/// [objc_allocateClassPair]: super::objc_allocateClassPair
+/// [objc_registerClassPair]: super::objc_registerClassPair
 #[no_mangle]
-pub extern "C" fn class_setIvarLayout(_cls: Class, _layout: *const u8) {
-    unimplemented!("no garbage collection support yet")
+pub extern "C" fn class_setIvarLayout(cls: Class, layout: *const u8) {
+    set_ivar_layout(cls, layout, Ownership::Strong)
 }
 
+/// Like [class_getIvarLayout], but for weak object pointers.
 #[no_mangle]
-pub extern "C" fn class_weakGetIvarLayout(_cls: Class) -> *const u8 {
-    unimplemented!("no garbage collection support yet")
+pub extern "C" fn class_weakGetIvarLayout(cls: Class) -> *const u8 {
+    get_ivar_layout(cls, Ownership::Weak)
 }
 
+/// Like [class_setIvarLayout], but marks the ivars as weak.
 #[no_mangle]
-pub extern "C" fn class_weakSetIvarLayout(_cls: Class, _layout: *const u8) {
-    unimplemented!("no garbage collection support yet")
+pub extern "C" fn class_weakSetIvarLayout(cls: Class, layout: *const u8) {
+    set_ivar_layout(cls, layout, Ownership::Weak)
 }
 
 #[no_mangle]
 pub extern "C" fn class_getProperty(cls: Class, name: *const c_char) -> Option<NonNull<Property>> {
     let mut cls = cls?;
 
-    let name = unsafe { CStr::from_ptr(name) }
-        .to_str()
-        .expect("invalid utf8");
+    let name = unsafe { CStr::from_ptr(name) };
 
     let property = unsafe { cls.as_mut() }
         .properties
         .iter_mut()
-        .find(|property| property.name == name)?;
+        .find(|property| property.name.as_c_str() == name)?;
 
     NonNull::new(property as *mut _)
 }
@@ -246,6 +296,56 @@ pub extern "C" fn class_copyPropertyList(
     )
 }
 
+/// Returns false if [cls] already has a property named [name].
+#[no_mangle]
+pub extern "C" fn class_addProperty(
+    cls: Class,
+    name: *const c_char,
+    attributes: *const objc_property_attribute_t,
+    attribute_count: c_uint,
+) -> bool {
+    let x: Option<()> = try {
+        let cls = unsafe { cls?.as_mut() };
+        let name = unsafe { CStr::from_ptr(name) };
+        if cls
+            .properties
+            .iter()
+            .any(|property| property.name.as_c_str() == name)
+        {
+            None?
+        }
+
+        cls.properties
+            .push(new_property(name, attributes, attribute_count));
+    };
+    x.is_some()
+}
+
+/// Replaces the attributes of [cls]'s property named [name], or adds the
+/// property if [cls] doesn't have one.
+#[no_mangle]
+pub extern "C" fn class_replaceProperty(
+    cls: Class,
+    name: *const c_char,
+    attributes: *const objc_property_attribute_t,
+    attribute_count: c_uint,
+) {
+    let _: Option<()> = try {
+        let cls = unsafe { cls?.as_mut() };
+        let name = unsafe { CStr::from_ptr(name) };
+        let property = new_property(name, attributes, attribute_count);
+
+        match cls
+            .properties
+            .iter_mut()
+            .find(|property| property.name.as_c_str() == name)
+        {
+            Some(existing) => *existing = property,
+            None => cls.properties.push(property),
+        }
+    };
+}
+
 #[no_mangle]
 pub extern "C" fn class_addMethod(cls: Class, name: SEL, imp: IMP, types: *const c_char) -> bool {
     let x: Option<()> = try {
@@ -257,14 +357,55 @@ pub extern "C" fn class_addMethod(cls: Class, name: SEL, imp: IMP, types: *const
             .into_string()
             .expect("invalid utf8");
 
-        cls.methods.push(objc_method::new(imp, name, types));
+        cls.add_method(name, imp, types);
+        CONTEXT.write().expect("poisoned rwlock").flush_caches();
+    };
+    x.is_some()
+}
+
+/// Adds [protocol] to the protocols [cls] adopts. Returns false if [cls]
+/// already conforms to it, including through a superclass.
+#[no_mangle]
+pub extern "C" fn class_addProtocol(cls: Class, protocol: Option<NonNull<Protocol>>) -> bool {
+    let x: Option<()> = try {
+        let cls = unsafe { cls?.as_mut() };
+        let protocol = unsafe { protocol?.as_ref() };
+        if CONTEXT
+            .read()
+            .expect("poisoned rwlock")
+            .class_conforms_to(cls.index, protocol.index)
+        {
+            None?
+        }
+
+        cls.protocols.push(protocol.index);
     };
     x.is_some()
 }
 
+#[no_mangle]
+pub extern "C" fn class_conformsToProtocol(
+    cls: Class,
+    protocol: Option<NonNull<Protocol>>,
+) -> bool {
+    let x: Option<bool> = try {
+        let cls = unsafe { cls?.as_ref() };
+        let protocol = unsafe { protocol?.as_ref() };
+        CONTEXT
+            .read()
+            .expect("poisoned rwlock")
+            .class_conforms_to(cls.index, protocol.index)
+    };
+    x.unwrap_or(false)
+}
+
 // TODO: match casing on (e.g.) [extra_bytes]
 #[no_mangle]
-pub extern "C" fn class_createInstance(cls: Class, _extra_bytes: libc::size_t) -> id {
-    // TODO: add [extra_bytes] to the layout
-    Some(unsafe { cls?.as_ref() }.create_object().cast())
+pub extern "C" fn class_createInstance(cls: Class, extra_bytes: libc::size_t) -> id {
+    let obj = unsafe { cls?.as_ref() }.create_object(extra_bytes);
+    if !construct_instance(obj) {
+        unsafe { objc_object::dispose(obj) };
+        return None;
+    }
+    Some(obj.cast())
 }
@@ -0,0 +1,168 @@
+use super::global_context::CONTEXT;
+use super::names::CATCH_ID;
+use super::objc_retain;
+use crate::runtime::{
+    dwarf::{self, Action, Bases},
+    exception::{self, objc_uncaught_exception_handler, ObjcException},
+    id,
+    unwind::*,
+};
+use std::{
+    ffi::{c_int, c_void, CStr},
+    ptr::NonNull,
+};
+
+/// Throws [object] as an Objective-C exception.
+#[no_mangle]
+pub extern "C-unwind" fn objc_exception_throw(object: id) -> ! {
+    let exception = ObjcException::allocate(objc_retain(object));
+    unsafe { _Unwind_RaiseException(exception) };
+    exception::uncaught(exception)
+}
+
+/// Rethrows [exception], as passed to [objc_begin_catch], from inside the
+/// catch clause or `@finally` block handling it.
+#[no_mangle]
+pub extern "C-unwind" fn objc_exception_rethrow(exception: *mut c_void) -> ! {
+    exception::rethrow(exception.cast())
+}
+
+/// Enters the catch clause for [exception], returning the thrown object.
+#[no_mangle]
+pub extern "C" fn objc_begin_catch(exception: *mut c_void) -> id {
+    exception::begin_catch(exception.cast())
+}
+
+/// Leaves the innermost catch clause, destroying its exception unless it was
+/// rethrown.
+#[no_mangle]
+pub extern "C" fn objc_end_catch() {
+    exception::end_catch()
+}
+
+/// Sets the function called with Objective-C exceptions that nothing catches,
+/// before the process aborts. Returns the previous handler.
+#[no_mangle]
+pub extern "C" fn objc_setUncaughtExceptionHandler(
+    handler: objc_uncaught_exception_handler,
+) -> objc_uncaught_exception_handler {
+    std::mem::replace(
+        &mut *exception::UNCAUGHT_HANDLER.lock().expect("poisoned mutex"),
+        handler,
+    )
+}
+
+/// Whether the catch clause whose type table entry is [type_info] catches
+/// [exception], which is [None] for foreign exceptions. Entries are null for
+/// catch-alls and otherwise point to the name of the class caught; subclasses
+/// are caught too.
+fn catches(type_info: usize, exception: Option<&ObjcException>) -> bool {
+    if type_info == 0 {
+        return true;
+    }
+    let Some(exception) = exception else {
+        return false;
+    };
+
+    let class_name = unsafe { CStr::from_ptr(std::ptr::with_exposed_provenance(type_info)) };
+    if class_name == CATCH_ID {
+        return true;
+    }
+
+    let context = CONTEXT.read().expect("poisoned rwlock");
+    let Some(&target) = context.registered_classes.get(class_name) else {
+        return false;
+    };
+    let class_key = exception.object.and_then(|obj| context.class_of(obj));
+    std::iter::successors(class_key, |&class_key| {
+        context.classes[class_key].superclass
+    })
+    .any(|class_key| class_key == target)
+}
+
+/// Hands [exception] and the selected catch clause to [landing_pad].
+unsafe fn install(
+    context: *mut _Unwind_Context,
+    exception: *mut _Unwind_Exception,
+    landing_pad: usize,
+    switch_value: isize,
+) -> _Unwind_Reason_Code {
+    let (exception_register, switch_register) = EH_RETURN_DATA_REGNOS;
+    _Unwind_SetGR(context, exception_register, exception.expose_provenance());
+    _Unwind_SetGR(context, switch_register, switch_value as usize);
+    _Unwind_SetIP(context, landing_pad);
+    _URC_INSTALL_CONTEXT
+}
+
+/// The personality routine of Objective-C frames with `@catch` or `@finally`
+/// blocks, which tells the unwinder what each frame does with an exception.
+///
+/// # Safety
+///
+/// Must only be called by the unwinder.
+#[no_mangle]
+pub unsafe extern "C" fn __gnu_objc_personality_v0(
+    version: c_int,
+    actions: c_int,
+    _exception_class: u64,
+    exception: *mut c_void,
+    context: *mut c_void,
+) -> c_int {
+    let exception = exception.cast::<_Unwind_Exception>();
+    let context = context.cast::<_Unwind_Context>();
+    if version != 1 {
+        return _URC_FATAL_PHASE1_ERROR;
+    }
+
+    let mut objc_exception = ObjcException::from_header(exception);
+
+    // The search phase already found the handler in this frame
+    if actions == _UA_CLEANUP_PHASE | _UA_HANDLER_FRAME {
+        if let Some(objc_exception) = &objc_exception {
+            let (landing_pad, switch_value) =
+                (objc_exception.landing_pad, objc_exception.switch_value);
+            return install(context, exception, landing_pad, switch_value);
+        }
+    }
+
+    let Some(lsda) = NonNull::new(_Unwind_GetLanguageSpecificData(context).cast_mut()) else {
+        return _URC_CONTINUE_UNWIND;
+    };
+
+    let mut ip_before_instruction = 0;
+    let mut ip = _Unwind_GetIPInfo(context, &mut ip_before_instruction);
+    // Look up the call instruction itself, not the one it returns to
+    if ip_before_instruction == 0 {
+        ip -= 1;
+    }
+
+    let bases = Bases {
+        func_start: _Unwind_GetRegionStart(context),
+        text: _Unwind_GetTextRelBase(context),
+        data: _Unwind_GetDataRelBase(context),
+    };
+    let action = dwarf::find_action(lsda.as_ptr().cast(), ip, &bases, |type_info| {
+        // Forced unwinds, like thread cancellation, run cleanups only
+        actions & _UA_FORCE_UNWIND == 0 && catches(type_info, objc_exception.as_deref())
+    });
+
+    match action {
+        None | Some(Action::Terminate) => exception::uncaught(exception),
+        Some(Action::None) => _URC_CONTINUE_UNWIND,
+        Some(Action::Cleanup(_)) if actions & _UA_SEARCH_PHASE != 0 => _URC_CONTINUE_UNWIND,
+        Some(Action::Cleanup(landing_pad)) => install(context, exception, landing_pad, 0),
+        Some(Action::Catch {
+            landing_pad,
+            switch_value,
+        }) => {
+            if actions & _UA_SEARCH_PHASE == 0 {
+                return install(context, exception, landing_pad, switch_value);
+            }
+            if let Some(objc_exception) = &mut objc_exception {
+                objc_exception.landing_pad = landing_pad;
+                objc_exception.switch_value = switch_value;
+            }
+            _URC_HANDLER_FOUND
+        }
+    }
+}
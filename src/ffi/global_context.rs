@@ -1,7 +1,11 @@
 use std::sync::LazyLock;
 use std::sync::RwLock;
 
+use crate::runtime::block;
 pub(crate) use crate::runtime::context::Context;
 
-pub(crate) static CONTEXT: LazyLock<RwLock<Context>> =
-    LazyLock::new(|| RwLock::new(Context::new()));
+pub(crate) static CONTEXT: LazyLock<RwLock<Context>> = LazyLock::new(|| {
+    let mut context = Context::new();
+    block::register_classes(&mut context);
+    RwLock::new(context)
+});
@@ -1,17 +1,37 @@
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
 
+pub mod accessors;
+pub mod arc;
+pub mod association;
+pub mod block;
+pub mod category;
 pub mod class;
 mod empty_string;
-mod global_context;
+pub mod exception;
+pub(crate) mod global_context;
+/// cbindgen:ignore
+pub(crate) mod names;
 pub mod objc;
 pub mod object;
+pub mod property;
+pub mod protocol;
 pub mod sel;
+pub mod sync;
 
+pub use accessors::*;
+pub use arc::*;
+pub use association::*;
+pub use block::*;
+pub use category::*;
 pub use class::*;
+pub use exception::*;
 pub use objc::*;
 pub use object::*;
+pub use property::*;
+pub use protocol::*;
 pub use sel::*;
+pub use sync::*;
 
 // TODO: null-check name pointers
 
@@ -19,9 +39,29 @@ pub use sel::*;
 mod tests {
     use empty_string::EMPTY_STRING;
 
-    use crate::runtime::{class::Class, id, selector::SEL};
-    use std::ffi::{c_uint, CString};
+    use crate::runtime::{
+        block::{
+            Block_descriptor, Block_layout, BLOCK_FIELD_IS_OBJECT, BLOCK_HAS_COPY_DISPOSE,
+            BLOCK_IS_GLOBAL,
+        },
+        class::Class,
+        class_builder::{ClassBuilder, ClassHandle},
+        closure::{add_closure_method, IntoMethod},
+        exception::ObjcException,
+        id,
+        ivar::{decode_layout, encode_layout, Ownership},
+        message::{Receiver, Repr},
+        objc_imp,
+        object::{objc_object, Object, ObjectData},
+        property::objc_property_attribute_t,
+        selector::SEL,
+        small_object::{SMALL_OBJECT_BITS, SMALL_OBJECT_MASK},
+    };
+    use serial_test::serial;
+    use std::collections::BTreeSet;
+    use std::ffi::{c_uint, c_void, CStr, CString};
     use std::ptr::NonNull;
+    use std::sync::atomic::AtomicI32;
 
     use super::*;
     #[test]
@@ -72,7 +112,7 @@ mod tests {
         let sel_name = CString::new("fizzbuzz").expect("valid utf8");
         let sel = unsafe { sel_registerName(sel_name.as_ptr()) };
 
-        unsafe extern "C" fn imp(self_: id, _cmd: SEL, ...) -> id {
+        unsafe extern "C" fn imp(self_: id, _cmd: SEL, _: ...) -> id {
             // TODO: do something with [_cmd] to make sure we're passing it correctly.
             self_
         }
@@ -123,4 +163,1415 @@ mod tests {
 
         assert_eq!(new_value, obj2);
     }
+
+    #[test]
+    fn test_attach_category() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        unsafe extern "C" fn original(_self: id, _cmd: SEL, _: ...) -> id {
+            None
+        }
+
+        unsafe extern "C" fn replacement(self_: id, _cmd: SEL, _: ...) -> id {
+            self_
+        }
+
+        static DESTRUCTED: AtomicBool = AtomicBool::new(false);
+        unsafe extern "C" fn destruct(_self: id, _cmd: SEL, _: ...) -> id {
+            DESTRUCTED.store(true, Ordering::SeqCst);
+            None
+        }
+
+        let cls_name = CString::new("foobar5").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+
+        let sel_name = CString::new("fizzbuzz").expect("valid utf8");
+        let sel = unsafe { sel_registerName(sel_name.as_ptr()) };
+        let types = EMPTY_STRING.as_ptr();
+        assert!(class_addMethod(cls, sel, Some(original), types));
+
+        let obj = class_createInstance(cls, 0);
+        let imp = objc_msg_lookup(obj, sel).expect("should be a real function");
+        assert_eq!(imp as usize, original as objc_imp as usize);
+
+        // Attaching to a registered class takes effect immediately and must
+        // not leave the old implementation cached.
+        let cat_name = CString::new("Replacement").expect("valid utf8");
+        let cat = objc_allocateCategory(cls_name.as_ptr(), cat_name.as_ptr());
+        assert!(category_addMethod(cat, sel, Some(replacement), types));
+        objc_attachCategory(cat);
+
+        let imp = objc_msg_lookup(obj, sel).expect("should be a real function");
+        assert_eq!(imp as usize, replacement as objc_imp as usize);
+
+        // Category properties come before the class' own
+        let property_name = CString::new("count").expect("valid utf8");
+        let attribute_name = CString::new("T").expect("valid utf8");
+        let value = CString::new("i").expect("valid utf8");
+        let attributes = [objc_property_attribute_t {
+            name: attribute_name.as_ptr(),
+            value: value.as_ptr(),
+        }];
+        assert!(class_addProperty(
+            cls,
+            property_name.as_ptr(),
+            attributes.as_ptr(),
+            1
+        ));
+
+        let cat = objc_allocateCategory(cls_name.as_ptr(), cat_name.as_ptr());
+        let value = CString::new("q").expect("valid utf8");
+        let attributes = [objc_property_attribute_t {
+            name: attribute_name.as_ptr(),
+            value: value.as_ptr(),
+        }];
+        assert!(category_addProperty(
+            cat,
+            property_name.as_ptr(),
+            attributes.as_ptr(),
+            1
+        ));
+        assert!(!category_addProperty(
+            cat,
+            property_name.as_ptr(),
+            std::ptr::null(),
+            0
+        ));
+        let attributes_of = |cls| {
+            let property = class_getProperty(cls, property_name.as_ptr());
+            unsafe { CStr::from_ptr(property_getAttributes(property)) }.to_owned()
+        };
+        assert_eq!(attributes_of(cls).as_bytes(), b"Ti");
+        objc_attachCategory(cat);
+        assert_eq!(attributes_of(cls).as_bytes(), b"Tq");
+
+        // A category's .cxx_destruct runs like one added to the class
+        let destruct_name = CString::new(".cxx_destruct").expect("valid utf8");
+        let destruct_sel = unsafe { sel_registerName(destruct_name.as_ptr()) };
+        let cat = objc_allocateCategory(cls_name.as_ptr(), cat_name.as_ptr());
+        assert!(category_addMethod(cat, destruct_sel, Some(destruct), types));
+        objc_attachCategory(cat);
+        object_dispose(obj);
+        assert!(DESTRUCTED.load(Ordering::SeqCst));
+
+        // Categories on classes that aren't registered yet wait for them.
+        let cls_name = CString::new("foobar6").expect("valid utf8");
+        let cat = objc_allocateCategory(cls_name.as_ptr(), cat_name.as_ptr());
+        assert!(category_addClassMethod(cat, sel, Some(replacement), types));
+        objc_attachCategory(cat);
+
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        let id = cls.map(NonNull::cast);
+        assert!(objc_msg_lookup(id, sel).is_none());
+
+        objc_registerClassPair(cls);
+        let imp = objc_msg_lookup(id, sel).expect("should be a real function");
+        assert_eq!(imp as usize, replacement as objc_imp as usize);
+    }
+
+    #[test]
+    fn test_allocate_protocol() {
+        let base_name = CString::new("FooBase").expect("valid utf8");
+        let base = objc_allocateProtocol(base_name.as_ptr());
+        assert!(base.is_some());
+        objc_registerProtocol(base);
+
+        // Registered names can't be reused
+        assert!(objc_allocateProtocol(base_name.as_ptr()).is_none());
+
+        let proto_name = CString::new("FooDerived").expect("valid utf8");
+        let proto = objc_allocateProtocol(proto_name.as_ptr());
+
+        let sel_name = CString::new("fizzbuzz").expect("valid utf8");
+        let sel = unsafe { sel_registerName(sel_name.as_ptr()) };
+        let types = CString::new("@@:").expect("valid utf8");
+        protocol_addMethodDescription(proto, sel, types.as_ptr(), true, true);
+        protocol_addProtocol(proto, base);
+
+        // Unregistered protocols can't be looked up yet
+        assert!(objc_getProtocol(proto_name.as_ptr()).is_none());
+        objc_registerProtocol(proto);
+        assert_eq!(objc_getProtocol(proto_name.as_ptr()), proto);
+        assert!(protocol_isEqual(
+            proto,
+            objc_getProtocol(proto_name.as_ptr())
+        ));
+        assert!(!protocol_isEqual(proto, base));
+
+        let name = unsafe { CStr::from_ptr(protocol_getName(proto)) };
+        assert_eq!(name, proto_name.as_c_str());
+
+        // Registered protocols are immutable
+        protocol_addMethodDescription(proto, sel, types.as_ptr(), true, true);
+
+        let mut out_count: c_uint = 0;
+        let descriptions =
+            protocol_copyMethodDescriptionList(proto, true, true, &mut out_count as *mut _)
+                .expect("should have a method");
+        assert_eq!(out_count, 1);
+        let description = unsafe { descriptions.as_ref() };
+        assert_eq!(description.name, sel);
+        assert_eq!(
+            unsafe { CStr::from_ptr(description.types) },
+            types.as_c_str()
+        );
+        unsafe {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                descriptions.as_ptr(),
+                out_count as usize,
+            )))
+        };
+
+        let output =
+            protocol_copyMethodDescriptionList(proto, false, true, &mut out_count as *mut _);
+        assert!(output.is_none());
+        assert_eq!(out_count, 0);
+
+        let protocols =
+            objc_copyProtocolList(&mut out_count as *mut _).expect("should have protocols");
+        let protocols = unsafe {
+            Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                protocols.as_ptr(),
+                out_count as usize,
+            ))
+        };
+        assert!(protocols.contains(&base.unwrap()));
+        assert!(protocols.contains(&proto.unwrap()));
+    }
+
+    #[test]
+    fn test_conforms_to_protocol() {
+        let base_name = CString::new("BarBase").expect("valid utf8");
+        let base = objc_allocateProtocol(base_name.as_ptr());
+        objc_registerProtocol(base);
+
+        let proto_name = CString::new("BarDerived").expect("valid utf8");
+        let proto = objc_allocateProtocol(proto_name.as_ptr());
+        protocol_addProtocol(proto, base);
+        objc_registerProtocol(proto);
+
+        assert!(protocol_conformsToProtocol(proto, base));
+        assert!(!protocol_conformsToProtocol(base, proto));
+
+        let cls_name = CString::new("foobar7").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let subclass_name = CString::new("foobar8").expect("valid utf8");
+        let subclass = objc_allocateClassPair(cls, subclass_name.as_ptr(), 0);
+        objc_registerClassPair(subclass);
+
+        assert!(!class_conformsToProtocol(subclass, base));
+
+        assert!(class_addProtocol(cls, proto));
+        // Adopting a protocol twice is rejected
+        assert!(!class_addProtocol(cls, proto));
+        assert!(!class_addProtocol(subclass, base));
+
+        assert!(class_conformsToProtocol(cls, proto));
+        assert!(class_conformsToProtocol(subclass, proto));
+        assert!(class_conformsToProtocol(subclass, base));
+    }
+
+    #[test]
+    fn test_add_property() {
+        let cls_name = CString::new("foobar9").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+
+        let name = CString::new("title").expect("valid utf8");
+        let type_name = CString::new("T").expect("valid utf8");
+        let type_value = CString::new("@\"NSString\"").expect("valid utf8");
+        let copy_name = CString::new("C").expect("valid utf8");
+        let ivar_name = CString::new("V").expect("valid utf8");
+        let ivar_value = CString::new("_title").expect("valid utf8");
+        let attributes = [
+            objc_property_attribute_t {
+                name: type_name.as_ptr(),
+                value: type_value.as_ptr(),
+            },
+            objc_property_attribute_t {
+                name: copy_name.as_ptr(),
+                value: EMPTY_STRING.as_ptr(),
+            },
+            objc_property_attribute_t {
+                name: ivar_name.as_ptr(),
+                value: ivar_value.as_ptr(),
+            },
+        ];
+
+        assert!(class_addProperty(
+            cls,
+            name.as_ptr(),
+            attributes.as_ptr(),
+            3
+        ));
+        // Duplicate names are rejected
+        assert!(!class_addProperty(
+            cls,
+            name.as_ptr(),
+            attributes.as_ptr(),
+            3
+        ));
+
+        let property = class_getProperty(cls, name.as_ptr());
+        assert!(property.is_some());
+        assert_eq!(
+            unsafe { CStr::from_ptr(property_getName(property)) },
+            name.as_c_str()
+        );
+        assert_eq!(
+            unsafe { CStr::from_ptr(property_getAttributes(property)) }
+                .to_str()
+                .unwrap(),
+            "T@\"NSString\",C,V_title"
+        );
+
+        let mut out_count: c_uint = 0;
+        let list = property_copyAttributeList(property, &mut out_count as *mut _)
+            .expect("should have attributes");
+        assert_eq!(out_count, 3);
+        let list = unsafe {
+            Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                list.as_ptr(),
+                out_count as usize,
+            ))
+        };
+        assert_eq!(
+            unsafe { CStr::from_ptr(list[2].name) },
+            ivar_name.as_c_str()
+        );
+        assert_eq!(
+            unsafe { CStr::from_ptr(list[2].value) },
+            ivar_value.as_c_str()
+        );
+
+        let value = property_copyAttributeValue(property, ivar_name.as_ptr());
+        assert_eq!(unsafe { CStr::from_ptr(value) }, ivar_value.as_c_str());
+        unsafe { libc::free(value.cast()) };
+        let missing = CString::new("R").expect("valid utf8");
+        assert!(property_copyAttributeValue(property, missing.as_ptr()).is_null());
+
+        // Replacing keeps a single property with the new attributes
+        class_replaceProperty(cls, name.as_ptr(), attributes.as_ptr(), 1);
+        let property = class_getProperty(cls, name.as_ptr());
+        assert_eq!(
+            unsafe { CStr::from_ptr(property_getAttributes(property)) }
+                .to_str()
+                .unwrap(),
+            "T@\"NSString\""
+        );
+        let properties =
+            class_copyPropertyList(cls, &mut out_count as *mut _).expect("should have properties");
+        assert_eq!(out_count, 1);
+        unsafe {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                properties.as_ptr(),
+                out_count as usize,
+            )))
+        };
+    }
+
+    #[test]
+    fn test_synthesized_accessors() {
+        let cls_name = CString::new("foobar10").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+
+        for name in ["first", "second"] {
+            let ivar_name = CString::new(name).expect("valid utf8");
+            class_addIvar(
+                cls,
+                ivar_name.as_ptr(),
+                std::mem::size_of::<id>(),
+                std::mem::size_of::<id>().ilog2() as u8,
+                EMPTY_STRING.as_ptr(),
+            );
+        }
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+        let second_name = CString::new("second").expect("valid utf8");
+        let offset = ivar_getOffset(class_getInstanceVariable(cls, second_name.as_ptr()));
+        assert_ne!(offset, 0);
+
+        // -copy returns a distinct instance so we can tell it was called
+        unsafe extern "C" fn copy(self_: id, _cmd: SEL, _: ...) -> id {
+            let cls = object_getClass(self_);
+            class_createInstance(cls, 0)
+        }
+        let copy_name = CString::new("copy").expect("valid utf8");
+        let copy_sel = unsafe { sel_registerName(copy_name.as_ptr()) };
+        assert!(class_addMethod(
+            cls,
+            copy_sel,
+            Some(copy),
+            EMPTY_STRING.as_ptr()
+        ));
+
+        let obj = class_createInstance(cls, 0);
+        let value = class_createInstance(cls, 0);
+
+        objc_setProperty_atomic(obj, None, value, offset);
+        assert_eq!(objc_getProperty(obj, None, offset, true), value);
+        assert_eq!(
+            object_getIvar(obj, class_getInstanceVariable(cls, second_name.as_ptr())),
+            value
+        );
+
+        objc_setProperty_nonatomic_copy(obj, None, value, offset);
+        let copied = objc_getProperty(obj, None, offset, false);
+        assert!(copied.is_some());
+        assert_ne!(copied, value);
+
+        objc_setProperty(obj, None, offset, value, true, 0);
+        assert_eq!(objc_getProperty(obj, None, offset, true), value);
+
+        let src: [u64; 3] = [1, 2, 3];
+        let mut dest: [u64; 3] = [0; 3];
+        objc_copyStruct(
+            dest.as_mut_ptr().cast(),
+            src.as_ptr().cast(),
+            std::mem::size_of_val(&src) as _,
+            true,
+            false,
+        );
+        assert_eq!(src, dest);
+    }
+
+    #[test]
+    fn test_dispose_object() {
+        use std::sync::Mutex;
+
+        static DESTRUCTED: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+        unsafe extern "C" fn destruct_base(_self: id, _cmd: SEL, _: ...) -> id {
+            DESTRUCTED.lock().unwrap().push("base");
+            None
+        }
+
+        unsafe extern "C" fn destruct_derived(_self: id, _cmd: SEL, _: ...) -> id {
+            DESTRUCTED.lock().unwrap().push("derived");
+            None
+        }
+
+        let base_name = CString::new("foobar11").expect("valid utf8");
+        let base = objc_allocateClassPair(None, base_name.as_ptr(), 0);
+        objc_registerClassPair(base);
+        let derived_name = CString::new("foobar12").expect("valid utf8");
+        let derived = objc_allocateClassPair(base, derived_name.as_ptr(), 0);
+        objc_registerClassPair(derived);
+
+        let sel_name = CString::new(".cxx_destruct").expect("valid utf8");
+        let sel = unsafe { sel_registerName(sel_name.as_ptr()) };
+        let types = EMPTY_STRING.as_ptr();
+        let base = objc_getClass(base_name.as_ptr()).map(NonNull::cast);
+        assert!(class_addMethod(base, sel, Some(destruct_base), types));
+        let derived = objc_getClass(derived_name.as_ptr()).map(NonNull::cast);
+        assert!(class_addMethod(derived, sel, Some(destruct_derived), types));
+
+        let obj = class_createInstance(derived, 0);
+        assert_eq!(object_getClass(obj), derived);
+        assert_eq!(object_dispose(obj), None);
+        assert_eq!(*DESTRUCTED.lock().unwrap(), ["derived", "base"]);
+
+        // Caller-provided storage is destructed but not freed
+        DESTRUCTED.lock().unwrap().clear();
+        let mut storage = vec![0u64; class_getInstanceSize(base).div_ceil(8)];
+        let obj = objc_constructInstance(base, storage.as_mut_ptr().cast());
+        assert_eq!(object_getClass(obj), base);
+        assert_eq!(objc_destructInstance(obj), storage.as_mut_ptr().cast());
+        assert_eq!(*DESTRUCTED.lock().unwrap(), ["base"]);
+    }
+
+    #[test]
+    fn test_cxx_construct() {
+        use std::sync::Mutex;
+
+        static CALLS: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+        unsafe extern "C" fn construct_base(self_: id, _cmd: SEL, _: ...) -> id {
+            CALLS.lock().unwrap().push("construct base");
+            self_
+        }
+
+        unsafe extern "C" fn construct_derived(self_: id, _cmd: SEL, _: ...) -> id {
+            CALLS.lock().unwrap().push("construct derived");
+            self_
+        }
+
+        unsafe extern "C" fn construct_failing(_self: id, _cmd: SEL, _: ...) -> id {
+            CALLS.lock().unwrap().push("construct failing");
+            None
+        }
+
+        unsafe extern "C" fn destruct_base(_self: id, _cmd: SEL, _: ...) -> id {
+            CALLS.lock().unwrap().push("destruct base");
+            None
+        }
+
+        let base_name = CString::new("foobar13").expect("valid utf8");
+        let base = objc_allocateClassPair(None, base_name.as_ptr(), 0);
+        objc_registerClassPair(base);
+        let derived_name = CString::new("foobar14").expect("valid utf8");
+        let derived = objc_allocateClassPair(base, derived_name.as_ptr(), 0);
+        objc_registerClassPair(derived);
+        let failing_name = CString::new("foobar15").expect("valid utf8");
+        let failing = objc_allocateClassPair(base, failing_name.as_ptr(), 0);
+        objc_registerClassPair(failing);
+
+        let construct_name = CString::new(".cxx_construct").expect("valid utf8");
+        let construct = unsafe { sel_registerName(construct_name.as_ptr()) };
+        let destruct_name = CString::new(".cxx_destruct").expect("valid utf8");
+        let destruct = unsafe { sel_registerName(destruct_name.as_ptr()) };
+        let types = EMPTY_STRING.as_ptr();
+
+        let base = objc_getClass(base_name.as_ptr()).map(NonNull::cast);
+        assert!(class_addMethod(
+            base,
+            construct,
+            Some(construct_base),
+            types
+        ));
+        assert!(class_addMethod(base, destruct, Some(destruct_base), types));
+        let derived = objc_getClass(derived_name.as_ptr()).map(NonNull::cast);
+        assert!(class_addMethod(
+            derived,
+            construct,
+            Some(construct_derived),
+            types
+        ));
+        let failing = objc_getClass(failing_name.as_ptr()).map(NonNull::cast);
+        assert!(class_addMethod(
+            failing,
+            construct,
+            Some(construct_failing),
+            types
+        ));
+
+        let obj = class_createInstance(derived, 0);
+        assert!(obj.is_some());
+        object_dispose(obj);
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            ["construct base", "construct derived", "destruct base"]
+        );
+
+        // A failed constructor unwinds the superclasses that were constructed
+        CALLS.lock().unwrap().clear();
+        assert!(class_createInstance(failing, 0).is_none());
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            ["construct base", "construct failing", "destruct base"]
+        );
+    }
+
+    #[test]
+    fn test_indexed_ivars() {
+        let cls_name = CString::new("foobar16").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+
+        let ivar_name = CString::new("fizzbuzz").expect("valid utf8");
+        class_addIvar(cls, ivar_name.as_ptr(), 1, 0, EMPTY_STRING.as_ptr());
+        let ivar = class_getInstanceVariable(cls, ivar_name.as_ptr());
+        let instance_size = class_getInstanceSize(cls);
+
+        let obj = class_createInstance(cls, 32);
+        let indexed = object_getIndexedIvars(obj).cast::<u8>();
+        assert_eq!(indexed.addr() % std::mem::align_of::<usize>(), 0);
+
+        let ivar_ptr =
+            unsafe { objc_object::ivar_ptr(obj.unwrap().cast(), ivar_getOffset(ivar) as usize) };
+        assert!(indexed > ivar_ptr);
+
+        // The whole tail is usable without clobbering the ivar
+        unsafe {
+            ivar_ptr.write(7);
+            std::ptr::write_bytes(indexed, 0xff, 32);
+            assert_eq!(ivar_ptr.read(), 7);
+        }
+
+        // Per-instance extra bytes don't change the class' instance size
+        assert_eq!(class_getInstanceSize(cls), instance_size);
+        object_dispose(obj);
+    }
+
+    #[test]
+    fn test_copy_and_set_class() {
+        let cls_name = CString::new("foobar17").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        let ivar_name = CString::new("fizzbuzz").expect("valid utf8");
+        class_addIvar(
+            cls,
+            ivar_name.as_ptr(),
+            std::mem::size_of::<id>(),
+            std::mem::size_of::<id>().ilog2() as u8,
+            EMPTY_STRING.as_ptr(),
+        );
+        objc_registerClassPair(cls);
+        let other_name = CString::new("foobar18").expect("valid utf8");
+        let other = objc_allocateClassPair(None, other_name.as_ptr(), 0);
+        objc_registerClassPair(other);
+
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+        let ivar = class_getInstanceVariable(cls, ivar_name.as_ptr());
+
+        let obj = class_createInstance(cls, 0);
+        let value = class_createInstance(cls, 0);
+        object_setIvar(obj, ivar, value);
+
+        let copy = object_copy(obj, 0);
+        assert!(copy.is_some());
+        assert_ne!(copy, obj);
+        assert_eq!(object_getClass(copy), cls);
+        assert_eq!(object_getIvar(copy, ivar), value);
+
+        let other = objc_getClass(other_name.as_ptr()).map(NonNull::cast);
+        assert_eq!(object_setClass(copy, other), cls);
+        assert_eq!(object_getClass(copy), other);
+        assert_eq!(
+            unsafe { CStr::from_ptr(object_getClassName(copy)) },
+            other_name.as_c_str()
+        );
+
+        object_dispose(copy);
+        object_dispose(obj);
+        object_dispose(value);
+    }
+
+    #[test]
+    fn test_retain_release() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DESTRUCTED: AtomicUsize = AtomicUsize::new(0);
+        static CUSTOM_RETAINS: AtomicUsize = AtomicUsize::new(0);
+
+        unsafe extern "C" fn destruct(_self: id, _cmd: SEL, _: ...) -> id {
+            DESTRUCTED.fetch_add(1, Ordering::SeqCst);
+            None
+        }
+
+        unsafe extern "C" fn retain(self_: id, _cmd: SEL, _: ...) -> id {
+            CUSTOM_RETAINS.fetch_add(1, Ordering::SeqCst);
+            _objc_rootRetain(self_)
+        }
+
+        let cls_name = CString::new("foobar19").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let custom_name = CString::new("foobar20").expect("valid utf8");
+        let custom = objc_allocateClassPair(cls, custom_name.as_ptr(), 0);
+        objc_registerClassPair(custom);
+
+        let destruct_name = CString::new(".cxx_destruct").expect("valid utf8");
+        let destruct_sel = unsafe { sel_registerName(destruct_name.as_ptr()) };
+        let types = EMPTY_STRING.as_ptr();
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+        assert!(class_addMethod(cls, destruct_sel, Some(destruct), types));
+
+        let obj = class_createInstance(cls, 0);
+        assert_eq!(objc_retainCount(obj), 1);
+        assert_eq!(objc_retain(obj), obj);
+        assert_eq!(objc_retainCount(obj), 2);
+        objc_release(obj);
+        assert_eq!(objc_retainCount(obj), 1);
+        assert_eq!(DESTRUCTED.load(Ordering::SeqCst), 0);
+
+        // Releasing the last reference deallocates
+        objc_release(obj);
+        assert_eq!(DESTRUCTED.load(Ordering::SeqCst), 1);
+
+        // Class objects aren't reference counted
+        let cls_id = cls.map(NonNull::cast);
+        assert_eq!(objc_retain(cls_id), cls_id);
+        objc_release(cls_id);
+
+        // Subclasses overriding -retain get it as a message
+        let retain_name = CString::new("retain").expect("valid utf8");
+        let retain_sel = unsafe { sel_registerName(retain_name.as_ptr()) };
+        let custom = objc_getClass(custom_name.as_ptr()).map(NonNull::cast);
+        assert!(class_addMethod(custom, retain_sel, Some(retain), types));
+
+        let obj = class_createInstance(custom, 0);
+        objc_retain(obj);
+        assert_eq!(CUSTOM_RETAINS.load(Ordering::SeqCst), 1);
+        assert_eq!(_objc_rootRetainCount(obj), 2);
+        objc_release(obj);
+        objc_release(obj);
+        assert_eq!(DESTRUCTED.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_autorelease_pool() {
+        use std::sync::Mutex;
+
+        static DESTRUCTED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+        unsafe extern "C" fn destruct(self_: id, _cmd: SEL, _: ...) -> id {
+            DESTRUCTED.lock().unwrap().push(self_.unwrap().addr().get());
+            None
+        }
+
+        let cls_name = CString::new("foobar21").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let destruct_name = CString::new(".cxx_destruct").expect("valid utf8");
+        let destruct_sel = unsafe { sel_registerName(destruct_name.as_ptr()) };
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+        assert!(class_addMethod(
+            cls,
+            destruct_sel,
+            Some(destruct),
+            EMPTY_STRING.as_ptr()
+        ));
+        let addr = |obj: id| obj.unwrap().addr().get();
+
+        let outer = objc_autoreleasePoolPush();
+        let first = objc_autorelease(class_createInstance(cls, 0));
+        let second = objc_autorelease(class_createInstance(cls, 0));
+        let (first, second) = (addr(first), addr(second));
+
+        let inner = objc_autoreleasePoolPush();
+        // Enough objects to spill onto more than one page
+        let inner_objects = (0..1000)
+            .map(|_| addr(objc_autorelease(class_createInstance(cls, 0))))
+            .collect::<Vec<_>>();
+        objc_autoreleasePoolPop(inner);
+        assert_eq!(
+            *DESTRUCTED.lock().unwrap(),
+            inner_objects.into_iter().rev().collect::<Vec<_>>()
+        );
+
+        DESTRUCTED.lock().unwrap().clear();
+        objc_autoreleasePoolPop(outer);
+        assert_eq!(*DESTRUCTED.lock().unwrap(), [second, first]);
+
+        // Popping a pool twice leaves the enclosing pools alone
+        DESTRUCTED.lock().unwrap().clear();
+        let outer = objc_autoreleasePoolPush();
+        let obj = addr(objc_autorelease(class_createInstance(cls, 0)));
+        let inner = objc_autoreleasePoolPush();
+        objc_autoreleasePoolPop(inner);
+        objc_autoreleasePoolPop(inner);
+        assert!(DESTRUCTED.lock().unwrap().is_empty());
+        objc_autoreleasePoolPop(outer);
+        assert_eq!(*DESTRUCTED.lock().unwrap(), [obj]);
+
+        // Pools left on a thread are drained when it exits
+        DESTRUCTED.lock().unwrap().clear();
+        let cls = cls.map(|cls| cls.addr());
+        let obj = std::thread::spawn(move || {
+            let cls = cls.map(|cls| NonNull::new(cls.get() as *mut _).unwrap());
+            objc_autoreleasePoolPush();
+            addr(objc_autorelease(class_createInstance(cls, 0)))
+        })
+        .join()
+        .unwrap();
+        assert_eq!(*DESTRUCTED.lock().unwrap(), [obj]);
+    }
+
+    #[test]
+    fn test_arc_entry_points() {
+        let cls_name = CString::new("foobar22").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+
+        let pool = objc_autoreleasePoolPush();
+        let obj = class_createInstance(cls, 0);
+        let other = class_createInstance(cls, 0);
+
+        let mut strong: id = None;
+        objc_storeStrong(&mut strong as *mut _, obj);
+        assert_eq!(strong, obj);
+        assert_eq!(objc_retainCount(obj), 2);
+        objc_storeStrong(&mut strong as *mut _, other);
+        assert_eq!(objc_retainCount(obj), 1);
+        assert_eq!(objc_retainCount(other), 2);
+        objc_storeStrong(&mut strong as *mut _, None);
+        assert_eq!(objc_retainCount(other), 1);
+
+        // A returned value claimed straight away is never autoreleased
+        assert_eq!(objc_retainAutoreleaseReturnValue(obj), obj);
+        assert_eq!(objc_retainAutoreleasedReturnValue(obj), obj);
+        assert_eq!(objc_retainCount(obj), 2);
+
+        // Claiming something else falls back to autorelease and retain
+        objc_retainAutoreleaseReturnValue(obj);
+        assert_eq!(objc_retainAutoreleasedReturnValue(other), other);
+        assert_eq!(objc_retainCount(obj), 3);
+        assert_eq!(objc_retainCount(other), 2);
+
+        objc_retainAutoreleaseReturnValue(other);
+        assert_eq!(objc_unsafeClaimAutoreleasedReturnValue(other), other);
+        assert_eq!(objc_retainCount(other), 2);
+
+        objc_retainAutorelease(other);
+        assert_eq!(objc_retainCount(other), 3);
+
+        objc_autoreleasePoolPop(pool);
+        assert_eq!(objc_retainCount(obj), 2);
+        assert_eq!(objc_retainCount(other), 2);
+    }
+
+    #[test]
+    fn test_weak_references() {
+        let cls_name = CString::new("foobar23").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+
+        let obj = class_createInstance(cls, 0);
+        let other = class_createInstance(cls, 0);
+
+        let mut weak: id = None;
+        assert_eq!(objc_initWeak(&mut weak as *mut _, obj), obj);
+
+        // Weak references don't keep their object alive
+        assert_eq!(objc_retainCount(obj), 1);
+        let loaded = objc_loadWeakRetained(&mut weak as *mut _);
+        assert_eq!(loaded, obj);
+        assert_eq!(objc_retainCount(obj), 2);
+        objc_release(loaded);
+
+        let mut copy: id = None;
+        let mut moved: id = None;
+        objc_copyWeak(&mut copy as *mut _, &mut weak as *mut _);
+        assert_eq!(copy, obj);
+        objc_moveWeak(&mut moved as *mut _, &mut copy as *mut _);
+        assert_eq!(copy, None);
+        assert_eq!(moved, obj);
+        assert_eq!(objc_retainCount(obj), 1);
+
+        // Re-pointing a weak variable unregisters it from the old object
+        let mut repointed: id = None;
+        objc_initWeak(&mut repointed as *mut _, obj);
+        assert_eq!(objc_storeWeak(&mut repointed as *mut _, other), other);
+
+        objc_release(obj);
+        assert_eq!(weak, None);
+        assert_eq!(moved, None);
+        assert_eq!(repointed, other);
+        assert_eq!(objc_loadWeakRetained(&mut weak as *mut _), None);
+
+        objc_destroyWeak(&mut repointed as *mut _);
+        assert_eq!(repointed, None);
+        objc_release(other);
+    }
+
+    #[test]
+    fn test_ivar_ownership() {
+        let cls_name = CString::new("foobar24").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+
+        let ivar_names = ["strong", "weak", "unknown"].map(|name| CString::new(name).unwrap());
+        let types = CString::new("@").expect("valid utf8");
+        for ivar_name in &ivar_names {
+            class_addIvar(
+                cls,
+                ivar_name.as_ptr(),
+                std::mem::size_of::<id>(),
+                std::mem::size_of::<id>().ilog2() as u8,
+                types.as_ptr(),
+            );
+        }
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+
+        let [strong, weak, unknown] =
+            ivar_names.map(|ivar_name| class_getInstanceVariable(cls, ivar_name.as_ptr()));
+        unsafe {
+            weak.unwrap().as_mut().ownership = Ownership::Weak;
+            unknown.unwrap().as_mut().ownership = Ownership::Invalid;
+        }
+
+        let obj = class_createInstance(cls, 0);
+        let value = class_createInstance(cls, 0);
+
+        object_setIvar(obj, strong, value);
+        assert_eq!(objc_retainCount(value), 2);
+        object_setIvar(obj, strong, None);
+        assert_eq!(objc_retainCount(value), 1);
+
+        // Unknown ivars are unretained unless the strong default is asked for
+        object_setIvar(obj, unknown, value);
+        assert_eq!(objc_retainCount(value), 1);
+        object_setIvar(obj, unknown, None);
+        object_setIvarWithStrongDefault(obj, unknown, value);
+        assert_eq!(objc_retainCount(value), 2);
+        object_setIvarWithStrongDefault(obj, unknown, None);
+
+        let pool = objc_autoreleasePoolPush();
+        object_setIvar(obj, weak, value);
+        assert_eq!(objc_retainCount(value), 1);
+        assert_eq!(object_getIvar(obj, weak), value);
+        objc_autoreleasePoolPop(pool);
+
+        objc_release(value);
+        assert_eq!(object_getIvar(obj, weak), None);
+
+        // Copies hold references of their own
+        let value = class_createInstance(cls, 0);
+        object_setIvar(obj, strong, value);
+        object_setIvar(obj, weak, value);
+        let copy = object_copy(obj, 0);
+        assert_eq!(objc_retainCount(value), 3);
+        let pool = objc_autoreleasePoolPush();
+        assert_eq!(object_getIvar(copy, weak), value);
+        objc_autoreleasePoolPop(pool);
+        object_setIvar(obj, strong, None);
+        object_setIvar(copy, strong, None);
+        objc_release(value);
+        assert_eq!(object_getIvar(obj, weak), None);
+        assert_eq!(object_getIvar(copy, weak), None);
+        objc_release(copy);
+
+        // Disposing an object releases its strong ivars and unregisters its
+        // weak ones
+        let value = class_createInstance(cls, 0);
+        object_setIvar(obj, strong, value);
+        object_setIvar(obj, weak, value);
+        assert_eq!(objc_retainCount(value), 2);
+        objc_release(obj);
+        assert_eq!(objc_retainCount(value), 1);
+        objc_release(value);
+    }
+
+    #[test]
+    fn test_ivar_layout() {
+        let cls_name = CString::new("foobar25").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+
+        let ivars = [
+            ("first", "@"),
+            ("count", "q"),
+            ("second", "@"),
+            ("delegate", "@"),
+        ]
+        .map(|(name, types)| (CString::new(name).unwrap(), CString::new(types).unwrap()));
+        for (name, types) in &ivars {
+            class_addIvar(
+                cls,
+                name.as_ptr(),
+                std::mem::size_of::<id>(),
+                std::mem::size_of::<id>().ilog2() as u8,
+                types.as_ptr(),
+            );
+        }
+
+        // Object ivars are strong until a layout says otherwise
+        let header = memoffset::offset_of!(Repr<ObjectData>, data) / std::mem::size_of::<id>();
+        let layout = class_getIvarLayout(cls);
+        assert_eq!(
+            unsafe { decode_layout(layout) },
+            BTreeSet::from([header, header + 2, header + 3])
+        );
+        assert!(class_weakGetIvarLayout(cls).is_null());
+
+        let weak_layout = encode_layout(&BTreeSet::from([header + 3]));
+        class_weakSetIvarLayout(cls, weak_layout.as_ptr());
+        let layout = class_getIvarLayout(cls);
+        assert_eq!(
+            unsafe { decode_layout(layout) },
+            BTreeSet::from([header, header + 2])
+        );
+        let layout = class_weakGetIvarLayout(cls);
+        assert_eq!(
+            unsafe { decode_layout(layout) },
+            BTreeSet::from([header + 3])
+        );
+
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+
+        // Layouts can't change once the class is in use
+        class_weakSetIvarLayout(cls, std::ptr::null());
+        assert!(!class_weakGetIvarLayout(cls).is_null());
+
+        let delegate = class_getInstanceVariable(cls, ivars[3].0.as_ptr());
+        let obj = class_createInstance(cls, 0);
+        let value = class_createInstance(cls, 0);
+        object_setIvar(obj, delegate, value);
+        objc_release(value);
+        assert_eq!(object_getIvar(obj, delegate), None);
+        objc_release(obj);
+    }
+
+    #[test]
+    fn test_associated_objects() {
+        unsafe extern "C" fn copy(self_: id, _cmd: SEL, _: ...) -> id {
+            object_copy(self_, 0)
+        }
+
+        let cls_name = CString::new("foobar26").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        let copy_name = CString::new("copy").expect("valid utf8");
+        class_addMethod(
+            cls,
+            unsafe { sel_registerName(copy_name.as_ptr()) },
+            Some(copy),
+            EMPTY_STRING.as_ptr(),
+        );
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+
+        static ASSIGNED: u8 = 0;
+        static RETAINED: u8 = 0;
+        static COPIED: u8 = 0;
+        let key = |key: &'static u8| key as *const u8 as *const c_void;
+
+        let obj = class_createInstance(cls, 0);
+        let value = class_createInstance(cls, 0);
+
+        objc_setAssociatedObject(obj, key(&ASSIGNED), value, OBJC_ASSOCIATION_ASSIGN);
+        assert_eq!(objc_getAssociatedObject(obj, key(&ASSIGNED)), value);
+        assert_eq!(objc_retainCount(value), 1);
+
+        objc_setAssociatedObject(
+            obj,
+            key(&RETAINED),
+            value,
+            OBJC_ASSOCIATION_RETAIN_NONATOMIC,
+        );
+        assert_eq!(objc_retainCount(value), 2);
+        objc_setAssociatedObject(obj, key(&RETAINED), None, OBJC_ASSOCIATION_RETAIN_NONATOMIC);
+        assert_eq!(objc_getAssociatedObject(obj, key(&RETAINED)), None);
+        assert_eq!(objc_retainCount(value), 1);
+
+        // Atomic getters hand out an autoreleased reference
+        objc_setAssociatedObject(obj, key(&RETAINED), value, OBJC_ASSOCIATION_RETAIN);
+        let pool = objc_autoreleasePoolPush();
+        assert_eq!(objc_getAssociatedObject(obj, key(&RETAINED)), value);
+        assert_eq!(objc_retainCount(value), 3);
+        objc_autoreleasePoolPop(pool);
+        assert_eq!(objc_retainCount(value), 2);
+
+        objc_setAssociatedObject(obj, key(&COPIED), value, OBJC_ASSOCIATION_COPY_NONATOMIC);
+        let copied = objc_getAssociatedObject(obj, key(&COPIED));
+        assert!(copied.is_some());
+        assert_ne!(copied, value);
+        let mut weak_copy: id = None;
+        objc_initWeak(&mut weak_copy as *mut _, copied);
+
+        // Destroying the object releases everything it owns
+        objc_release(obj);
+        assert_eq!(objc_retainCount(value), 1);
+        assert_eq!(weak_copy, None);
+
+        objc_setAssociatedObject(value, key(&RETAINED), value, OBJC_ASSOCIATION_ASSIGN);
+        objc_removeAssociatedObjects(value);
+        assert_eq!(objc_getAssociatedObject(value, key(&RETAINED)), None);
+        objc_release(value);
+    }
+
+    #[test]
+    fn test_small_objects() {
+        unsafe extern "C" fn int_value(self_: id, _cmd: SEL, _: ...) -> id {
+            // Hand the payload back as the "object" so the test can see it
+            NonNull::new(std::ptr::without_provenance_mut(
+                self_.unwrap().as_ptr().addr() >> SMALL_OBJECT_BITS,
+            ))
+        }
+
+        let cls_name = CString::new("foobar27").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        let ivar_name = CString::new("unused").expect("valid utf8");
+        class_addIvar(
+            cls,
+            ivar_name.as_ptr(),
+            std::mem::size_of::<id>(),
+            std::mem::size_of::<id>().ilog2() as u8,
+            EMPTY_STRING.as_ptr(),
+        );
+        let sel_name = CString::new("intValue").expect("valid utf8");
+        let sel = unsafe { sel_registerName(sel_name.as_ptr()) };
+        class_addMethod(cls, sel, Some(int_value), EMPTY_STRING.as_ptr());
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+
+        assert!(!objc_registerSmallObjectClass_np(cls, 0));
+        assert!(!objc_registerSmallObjectClass_np(
+            cls,
+            SMALL_OBJECT_MASK + 1
+        ));
+        assert!(objc_registerSmallObjectClass_np(cls, 5));
+
+        let small: id = NonNull::new(std::ptr::without_provenance_mut(
+            42 << SMALL_OBJECT_BITS | 5,
+        ));
+        assert_eq!(object_getClass(small), cls);
+
+        let imp = objc_msg_lookup(small, sel).expect("method should be found");
+        let value = unsafe { imp(small, sel) };
+        assert_eq!(value.map(|value| value.as_ptr().addr()), Some(42));
+
+        // Nothing is ever read from or written to the small object's "address"
+        assert_eq!(objc_retain(small), small);
+        objc_release(small);
+        assert_eq!(objc_autorelease(small), small);
+        let ivar = class_getInstanceVariable(cls, ivar_name.as_ptr());
+        object_setIvar(small, ivar, small);
+        assert_eq!(object_getIvar(small, ivar), None);
+
+        let mut weak: id = None;
+        objc_initWeak(&mut weak as *mut _, small);
+        assert_eq!(objc_loadWeakRetained(&mut weak as *mut _), small);
+        objc_destroyWeak(&mut weak as *mut _);
+
+        // Tags without a class have nothing to send messages to
+        let unregistered: id = NonNull::new(std::ptr::without_provenance_mut(
+            42 << SMALL_OBJECT_BITS | 6,
+        ));
+        assert_eq!(object_getClass(unregistered), None);
+        assert!(objc_msg_lookup(unregistered, sel).is_none());
+    }
+
+    #[test]
+    fn test_catch_releases_exception() {
+        let cls_name = CString::new("foobar28").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+
+        let obj = class_createInstance(cls, 0);
+        let mut weak: id = None;
+        objc_initWeak(&mut weak as *mut _, obj);
+
+        // What a landing pad would be handed for `@throw obj`
+        let exception = ObjcException::allocate(objc_retain(obj)).cast();
+        objc_release(obj);
+        assert_eq!(objc_begin_catch(exception), obj);
+
+        // A rethrown exception outlives the catch clause it leaves
+        ObjcException::from_header(exception.cast())
+            .unwrap()
+            .rethrown = true;
+        objc_end_catch();
+        assert_eq!(weak, obj);
+
+        assert_eq!(objc_begin_catch(exception), obj);
+        objc_end_catch();
+        assert_eq!(weak, None);
+    }
+
+    #[test]
+    fn test_uncaught_exception_handler() {
+        extern "C" fn first(_: id) {}
+        extern "C" fn second(_: id) {}
+
+        assert!(objc_setUncaughtExceptionHandler(Some(first)).is_none());
+        let previous = objc_setUncaughtExceptionHandler(Some(second));
+        assert_eq!(
+            previous.map(|handler| handler as usize),
+            Some(first as extern "C" fn(id) as usize)
+        );
+        objc_setUncaughtExceptionHandler(None);
+    }
+
+    #[test]
+    fn test_sync_enter_exit() {
+        let cls_name = CString::new("foobar29").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+        let obj = class_createInstance(cls, 0);
+        let address = obj.unwrap().as_ptr().addr();
+        let obj_on_other_thread =
+            move || NonNull::new(std::ptr::with_exposed_provenance_mut::<Receiver>(address));
+
+        // Locks are recursive
+        assert_eq!(objc_sync_enter(obj), OBJC_SYNC_SUCCESS);
+        assert_eq!(objc_sync_enter(obj), OBJC_SYNC_SUCCESS);
+
+        let entered = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let other = std::thread::spawn({
+            let entered = entered.clone();
+            move || {
+                let obj = obj_on_other_thread();
+                assert_eq!(objc_sync_exit(obj), OBJC_SYNC_NOT_OWNING_THREAD_ERROR);
+                assert_eq!(objc_sync_enter(obj), OBJC_SYNC_SUCCESS);
+                entered.store(true, std::sync::atomic::Ordering::SeqCst);
+                assert_eq!(objc_sync_exit(obj), OBJC_SYNC_SUCCESS);
+            }
+        });
+
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(objc_sync_exit(obj), OBJC_SYNC_SUCCESS);
+        assert!(!entered.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(objc_sync_exit(obj), OBJC_SYNC_SUCCESS);
+
+        other.join().unwrap();
+        assert!(entered.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(objc_sync_exit(obj), OBJC_SYNC_NOT_OWNING_THREAD_ERROR);
+        assert_eq!(objc_sync_enter(None), OBJC_SYNC_SUCCESS);
+        assert_eq!(objc_sync_exit(None), OBJC_SYNC_SUCCESS);
+    }
+
+    #[test]
+    fn test_blocks() {
+        #[repr(C)]
+        struct CapturingBlock {
+            layout: Block_layout,
+            captured: id,
+        }
+        unsafe extern "C" fn copy_helper(dst: *mut c_void, src: *const c_void) {
+            let dst = dst.cast::<CapturingBlock>();
+            let captured = (*src.cast::<CapturingBlock>()).captured;
+            _Block_object_assign(
+                std::ptr::addr_of_mut!((*dst).captured).cast(),
+                captured.map_or(std::ptr::null(), |obj| obj.as_ptr().cast()),
+                BLOCK_FIELD_IS_OBJECT,
+            );
+        }
+        unsafe extern "C" fn dispose_helper(src: *const c_void) {
+            let captured = (*src.cast::<CapturingBlock>()).captured;
+            _Block_object_dispose(
+                captured.map_or(std::ptr::null(), |obj| obj.as_ptr().cast()),
+                BLOCK_FIELD_IS_OBJECT,
+            );
+        }
+        static DESCRIPTOR: Block_descriptor = Block_descriptor {
+            reserved: 0,
+            size: std::mem::size_of::<CapturingBlock>(),
+            copy: Some(copy_helper),
+            dispose: Some(dispose_helper),
+        };
+        let class_name = |obj| {
+            unsafe { CStr::from_ptr(object_getClassName(obj)) }
+                .to_str()
+                .unwrap()
+        };
+
+        let cls_name = CString::new("foobar30").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+        let obj = class_createInstance(cls, 0);
+
+        let mut stack_block = CapturingBlock {
+            layout: Block_layout {
+                isa: std::ptr::addr_of!(_NSConcreteStackBlock).cast(),
+                flags: AtomicI32::new(BLOCK_HAS_COPY_DISPOSE),
+                reserved: 0,
+                invoke: std::ptr::null(),
+                descriptor: &DESCRIPTOR,
+            },
+            captured: obj,
+        };
+        let stack_block = NonNull::new(std::ptr::addr_of_mut!(stack_block).cast::<Receiver>());
+        assert_eq!(class_name(stack_block), "__NSStackBlock__");
+
+        // Retaining a stack block does nothing, but copying it moves it and
+        // what it captured to the heap
+        assert_eq!(objc_retain(stack_block), stack_block);
+        let block = objc_retainBlock(stack_block);
+        assert_ne!(block, stack_block);
+        assert_eq!(class_name(block), "__NSMallocBlock__");
+        assert_eq!(
+            unsafe { block.unwrap().cast::<CapturingBlock>().as_ref() }.captured,
+            obj
+        );
+        assert_eq!(objc_retainCount(obj), 2);
+
+        let copy = unsafe { sel_registerName(CString::new("copy").unwrap().as_ptr()) };
+        let copy_imp = objc_msg_lookup(block, copy).unwrap();
+        assert_eq!(unsafe { copy_imp(block, copy) }, block);
+        assert_eq!(objc_retain(block), block);
+        assert_eq!(objc_retainCount(block), 3);
+        _Block_release(block.unwrap().as_ptr().cast());
+        objc_release(block);
+        assert_eq!(objc_retainCount(obj), 2);
+        objc_release(block);
+        assert_eq!(objc_retainCount(obj), 1);
+        objc_release(obj);
+
+        let mut global_block = Block_layout {
+            isa: std::ptr::addr_of!(_NSConcreteGlobalBlock).cast(),
+            flags: AtomicI32::new(BLOCK_IS_GLOBAL),
+            reserved: 0,
+            invoke: std::ptr::null(),
+            descriptor: &DESCRIPTOR,
+        };
+        let global_block = NonNull::new(std::ptr::addr_of_mut!(global_block).cast::<Receiver>());
+        assert_eq!(class_name(global_block), "__NSGlobalBlock__");
+
+        // Blocks keep their class, and can only be copied with _Block_copy
+        assert_eq!(object_setClass(global_block, cls), None);
+        assert_eq!(class_name(global_block), "__NSGlobalBlock__");
+        assert_eq!(object_copy(global_block, 0), None);
+        let ivar_name = CString::new("captured").expect("valid utf8");
+        let mut value = std::ptr::null_mut();
+        assert!(object_getInstanceVariable(global_block, ivar_name.as_ptr(), &mut value).is_none());
+
+        assert_eq!(objc_retainBlock(global_block), global_block);
+        objc_release(global_block);
+    }
+
+    #[test]
+    #[serial]
+    fn test_imp_implementation_with_block() {
+        #[repr(C)]
+        struct AddingBlock {
+            layout: Block_layout,
+            receiver: id,
+            addend: usize,
+        }
+        unsafe extern "C" fn invoke(block: *mut AddingBlock, self_: id, value: usize) -> usize {
+            assert_eq!(self_, (*block).receiver);
+            value + (*block).addend
+        }
+        static DESCRIPTOR: Block_descriptor = Block_descriptor {
+            reserved: 0,
+            size: std::mem::size_of::<AddingBlock>(),
+            copy: None,
+            dispose: None,
+        };
+
+        let cls_name = CString::new("foobar31").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+        let obj = class_createInstance(cls, 0);
+
+        let mut block = AddingBlock {
+            layout: Block_layout {
+                isa: std::ptr::addr_of!(_NSConcreteStackBlock).cast(),
+                flags: AtomicI32::new(0),
+                reserved: 0,
+                invoke: invoke as *const c_void,
+                descriptor: &DESCRIPTOR,
+            },
+            receiver: obj,
+            addend: 40,
+        };
+        let block = NonNull::new(std::ptr::addr_of_mut!(block).cast::<Receiver>());
+
+        // The block is copied to the heap
+        let imp = imp_implementationWithBlock(block);
+        let heap_block = imp_getBlock(imp);
+        assert!(heap_block.is_some());
+        assert_ne!(heap_block, block);
+
+        let name = CString::new("add:").expect("valid utf8");
+        let sel = unsafe { sel_registerName(name.as_ptr()) };
+        let types = CString::new("Q24@0:8Q16").expect("valid utf8");
+        assert!(class_addMethod(cls, sel, imp, types.as_ptr()));
+        let add = unsafe {
+            std::mem::transmute::<objc_imp, unsafe extern "C" fn(id, SEL, usize) -> usize>(
+                objc_msg_lookup(obj, sel).unwrap(),
+            )
+        };
+        assert_eq!(unsafe { add(obj, sel, 2) }, 42);
+
+        // Only implementations made from blocks can be removed
+        unsafe extern "C" fn invoke_method(self_: id, _cmd: SEL, _: ...) -> id {
+            self_
+        }
+
+        assert!(imp_removeBlock(imp));
+        assert!(imp_getBlock(imp).is_none());
+        assert!(!imp_removeBlock(imp));
+        assert!(!imp_removeBlock(Some(invoke_method)));
+    }
+
+    #[test]
+    #[serial]
+    fn test_closure_methods() {
+        fn types<Args, Ret, F: IntoMethod<Args, Ret>>(_: &F) -> CString {
+            F::types()
+        }
+
+        let cls_name = CString::new("foobar32").expect("valid utf8");
+        let cls = objc_allocateClassPair(None, cls_name.as_ptr(), 0);
+        objc_registerClassPair(cls);
+        let cls = objc_getClass(cls_name.as_ptr()).map(NonNull::cast);
+        let obj = class_createInstance(cls, 0);
+
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let address = obj.unwrap().as_ptr().addr();
+        let scale = {
+            let calls = calls.clone();
+            move |receiver: &Object, value: u32, factor: f64| -> f64 {
+                assert_eq!(receiver.as_id().unwrap().as_ptr().addr(), address);
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                value as f64 * factor
+            }
+        };
+        assert_eq!(types(&scale).as_bytes(), b"d@:Id");
+        let name = CString::new("scale:by:").expect("valid utf8");
+        assert!(unsafe { add_closure_method(cls, &name, scale) });
+
+        let sel = unsafe { sel_registerName(name.as_ptr()) };
+        let scale = unsafe {
+            std::mem::transmute::<objc_imp, unsafe extern "C" fn(id, SEL, u32, f64) -> f64>(
+                objc_msg_lookup(obj, sel).unwrap(),
+            )
+        };
+        assert_eq!(unsafe { scale(obj, sel, 3, 1.5) }, 4.5);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Removing the implementation drops the closure
+        assert_eq!(std::sync::Arc::strong_count(&calls), 2);
+        assert!(imp_removeBlock(objc_msg_lookup(obj, sel)));
+        assert_eq!(std::sync::Arc::strong_count(&calls), 1);
+
+        let describe = |receiver: &Object| receiver.as_id();
+        assert_eq!(types(&describe).as_bytes(), b"@@:");
+        let name = CString::new("self").expect("valid utf8");
+        assert!(unsafe { add_closure_method(cls, &name, describe) });
+        let sel = unsafe { sel_registerName(name.as_ptr()) };
+        let imp = objc_msg_lookup(obj, sel).unwrap();
+        assert_eq!(unsafe { imp(obj, sel) }, obj);
+    }
+
+    #[test]
+    #[serial]
+    fn test_class_builder() {
+        use std::sync::Arc;
+
+        let proto_name = CString::new("foobar33Protocol").expect("valid utf8");
+        let proto = objc_allocateProtocol(proto_name.as_ptr());
+        objc_registerProtocol(proto);
+
+        let mut builder = ClassBuilder::new("foobar33", None).unwrap();
+        assert!(builder.add_ivar::<u8>("flag"));
+        assert!(builder.add_ivar::<f64>("value"));
+        assert!(!builder.add_ivar::<u8>("flag"));
+        assert!(builder.add_method("double:", |_: &Object, value: i32| value * 2));
+        assert!(builder.add_protocol("foobar33Protocol"));
+        assert!(!builder.add_protocol("foobar33Protocol"));
+        assert!(!builder.add_protocol("foobar33Missing"));
+        let cls = builder.register();
+
+        assert!(ClassBuilder::new("foobar33", None).is_none());
+        assert!(ClassHandle::lookup("foobar33") == Some(cls));
+        assert_eq!(cls.name().to_str().unwrap(), "foobar33");
+        assert!(class_conformsToProtocol(cls.as_class(), proto));
+
+        // The f64 is aligned past the u8
+        let ivar_name = CString::new("value").expect("valid utf8");
+        let ivar = class_getInstanceVariable(cls.as_class(), ivar_name.as_ptr());
+        assert_eq!(ivar_getOffset(ivar), 8);
+
+        let obj = cls.create_instance();
+        let name = CString::new("double:").expect("valid utf8");
+        let sel = unsafe { sel_registerName(name.as_ptr()) };
+        let double = unsafe {
+            std::mem::transmute::<objc_imp, unsafe extern "C" fn(id, SEL, i32) -> i32>(
+                objc_msg_lookup(obj, sel).unwrap(),
+            )
+        };
+        assert_eq!(unsafe { double(obj, sel, 21) }, 42);
+        objc_release(obj);
+
+        // A class that's never registered is thrown away with its methods
+        let captured = Arc::new(());
+        let mut builder = ClassBuilder::new("foobar34", None).unwrap();
+        let closure_captured = captured.clone();
+        assert!(builder.add_method("captured", move |_: &Object| {
+            Arc::strong_count(&closure_captured) as u64
+        }));
+        assert_eq!(Arc::strong_count(&captured), 2);
+        drop(builder);
+        assert_eq!(Arc::strong_count(&captured), 1);
+        assert!(ClassHandle::lookup("foobar34").is_none());
+    }
 }
@@ -0,0 +1,21 @@
+//! Names the runtime passes to itself as C strings. They're kept apart from
+//! the FFI modules because cbindgen's parser predates `c""` literals and
+//! can't read a file containing one.
+
+use std::ffi::CStr;
+
+pub(crate) const RETAIN: &CStr = c"retain";
+pub(crate) const RELEASE: &CStr = c"release";
+pub(crate) const AUTORELEASE: &CStr = c"autorelease";
+pub(crate) const RETAIN_COUNT: &CStr = c"retainCount";
+pub(crate) const DEALLOC: &CStr = c"dealloc";
+pub(crate) const COPY: &CStr = c"copy";
+pub(crate) const MUTABLE_COPY: &CStr = c"mutableCopy";
+
+/// Type table entry of `@catch (id e)`, which catches any Objective-C object
+/// but not foreign exceptions.
+pub(crate) const CATCH_ID: &CStr = c"@id";
+
+pub(crate) const CXA_BEGIN_CATCH: &CStr = c"__cxa_begin_catch";
+pub(crate) const CXA_END_CATCH: &CStr = c"__cxa_end_catch";
+pub(crate) const CXA_RETHROW: &CStr = c"__cxa_rethrow";
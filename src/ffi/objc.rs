@@ -1,4 +1,5 @@
 use super::global_context::CONTEXT;
+use super::sel_registerName;
 
 use crate::runtime::{id, method::IMP, Class, SEL};
 
@@ -39,12 +40,28 @@ pub extern "C" fn objc_registerClassPair(cls: Class) {
             CONTEXT
                 .write()
                 .expect("poisoned rwlock")
-                .registered_classes
-                .insert(cls.name.clone(), cls.index);
+                .register_class_pair(cls.index);
         }
     }
 }
 
+/// Makes [cls] the class of small objects whose low [SMALL_OBJECT_BITS] bits
+/// equal [tag]. Returns false if [tag] is zero or out of range, or if another
+/// class already uses it.
+///
+/// [SMALL_OBJECT_BITS]: crate::runtime::small_object::SMALL_OBJECT_BITS
+#[no_mangle]
+pub extern "C" fn objc_registerSmallObjectClass_np(cls: Class, tag: usize) -> bool {
+    let Some(cls) = cls else {
+        return false;
+    };
+    let cls = unsafe { cls.as_ref() };
+    CONTEXT
+        .write()
+        .expect("poisoned rwlock")
+        .register_small_object_class(cls.index, tag)
+}
+
 #[no_mangle]
 pub extern "C" fn objc_getMetaClass(name: *const c_char) -> id {
     let name = unsafe { CStr::from_ptr(name) };
@@ -54,11 +71,18 @@ pub extern "C" fn objc_getMetaClass(name: *const c_char) -> id {
     NonNull::new(&mut context.classes[metaclass_key] as *mut _).map(NonNull::cast)
 }
 
+/// Sends the argument-less message [name] to [receiver]. Returns nil if
+/// [receiver] is nil or doesn't respond to [name].
+pub(crate) fn send_message(receiver: id, name: &CStr) -> id {
+    let sel = unsafe { sel_registerName(name.as_ptr()) };
+    let imp = objc_msg_lookup(receiver, sel)?;
+    unsafe { imp(receiver, sel) }
+}
+
 pub extern "C" fn objc_msg_lookup(receiver: id, sel: SEL) -> IMP {
-    let receiver = unsafe { receiver?.as_ref() };
+    let receiver = receiver?;
     let sel = unsafe { sel?.as_ref() };
-    CONTEXT.write().expect("poisoned rwlock").classes[**receiver]
-        .methods
-        .iter_mut()
-        .find_map(|method| (method.selector == sel.index).then_some(method.imp))
+    let mut context = CONTEXT.write().expect("poisoned rwlock");
+    let class_key = context.class_of(receiver)?;
+    context.lookup_method(class_key, sel.index)
 }
@@ -1,29 +1,231 @@
+use super::association;
 use super::global_context::CONTEXT;
+use super::{
+    class_getName, objc_copyWeak, objc_loadWeak, objc_retain, objc_storeStrong, objc_storeWeak,
+};
 use crate::runtime::{
     class::Class,
+    context::ClassKey,
     id,
-    ivar::{objc_ivar, Ivar},
+    ivar::{objc_ivar, Ivar, Ownership},
     message::Receiver,
     object::objc_object,
+    refcount, small_object,
 };
 use libc::ptrdiff_t;
 use std::ffi::{c_char, c_void, CStr};
 use std::ptr::NonNull;
 
+/// Reads an object ivar. Weak ivars are loaded with [objc_loadWeak].
 #[no_mangle]
 pub extern "C" fn object_getIvar(obj: id, ivar: Ivar) -> id {
-    unsafe {
-        let ivar = { ivar?.as_ref() };
-        std::ptr::read(
-            (&obj?.cast::<objc_object>().as_mut().ivars[ivar.offset..]).as_ptr() as *const _,
+    let obj = small_object::in_memory(obj)?;
+    let ivar = unsafe { ivar?.as_ref() };
+    let location = unsafe { objc_object::ivar_ptr(obj.cast(), ivar.offset) }.cast::<id>();
+
+    match ivar.ownership {
+        Ownership::Weak => objc_loadWeak(location),
+        _ => unsafe { location.read() },
+    }
+}
+
+/// Runs [obj]'s `.cxx_construct` methods from the root class down. If one
+/// fails, the parts that were already constructed are destructed again and
+/// false is returned.
+pub(crate) fn construct_instance(obj: NonNull<objc_object>) -> bool {
+    let class_key = unsafe { obj.as_ref() }.is_a();
+    let (sel, constructors) = {
+        let mut context = CONTEXT.write().expect("poisoned rwlock");
+        let (selector, constructors) = context.cxx_constructors(class_key);
+        (
+            NonNull::new(&mut context.selectors[selector] as *mut _),
+            constructors,
+        )
+    };
+
+    for (class_key, constructor) in constructors {
+        if unsafe { constructor(Some(obj.cast()), sel) }.is_none() {
+            let superclass = CONTEXT.read().expect("poisoned rwlock").classes[class_key].superclass;
+            if let Some(superclass) = superclass {
+                destruct_from_class(obj, superclass);
+            }
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Runs the `.cxx_destruct` methods of [class_key] and its superclasses on
+/// [obj].
+fn destruct_from_class(obj: NonNull<objc_object>, class_key: ClassKey) {
+    let (sel, destructors) = {
+        let mut context = CONTEXT.write().expect("poisoned rwlock");
+        let (selector, destructors) = context.cxx_destructors(class_key);
+        (
+            NonNull::new(&mut context.selectors[selector] as *mut _),
+            destructors,
         )
+    };
+
+    for destructor in destructors {
+        unsafe { destructor(Some(obj.cast()), sel) };
     }
 }
 
+/// The class of [obj]. Unlike its isa, this is right for blocks too.
+fn class_of(obj: NonNull<Receiver>) -> Option<ClassKey> {
+    CONTEXT.read().expect("poisoned rwlock").class_of(obj)
+}
+
+/// Offsets of the [ownership] ivars declared by [class_key] and its
+/// superclasses.
+fn ivar_offsets(class_key: ClassKey, ownership: Ownership) -> Vec<usize> {
+    let context = CONTEXT.read().expect("poisoned rwlock");
+    let classes = std::iter::successors(Some(class_key), |&class_key| {
+        context.classes[class_key].superclass
+    });
+
+    classes
+        .flat_map(|class_key| &context.classes[class_key].ivars)
+        .filter(|ivar| ivar.ownership == ownership)
+        .map(|ivar| ivar.offset)
+        .collect()
+}
+
+/// Turns [bytes], which must be zero-filled, suitably aligned and at least
+/// [class_getInstanceSize] bytes long, into an instance of [cls].
+///
+/// [class_getInstanceSize]: super::class_getInstanceSize
+#[no_mangle]
+pub extern "C" fn objc_constructInstance(cls: Class, bytes: *mut c_void) -> id {
+    let cls = unsafe { cls?.as_ref() };
+    let mut obj = NonNull::new(bytes.cast::<objc_object>())?;
+    unsafe { obj.as_mut() }.set__is_a(cls.index);
+    construct_instance(obj).then_some(obj.cast())
+}
+
+/// Runs [obj]'s `.cxx_destruct` methods from its class up, releases its
+/// strong ivars, unregisters its weak ivars and releases its associated
+/// objects, without freeing it. Returns [obj].
+#[no_mangle]
+pub extern "C" fn objc_destructInstance(obj: id) -> *mut c_void {
+    let in_memory = small_object::in_memory(obj);
+    if let Some((obj, class_key)) = in_memory.zip(in_memory.and_then(class_of)) {
+        destruct_from_class(obj.cast(), class_key);
+        let ivar = |offset| unsafe { objc_object::ivar_ptr(obj.cast(), offset) }.cast::<id>();
+        for offset in ivar_offsets(class_key, Ownership::Strong) {
+            objc_storeStrong(ivar(offset), None);
+        }
+        for offset in ivar_offsets(class_key, Ownership::Weak) {
+            objc_storeWeak(ivar(offset), None);
+        }
+        association::remove_all(Some(obj));
+        refcount::clear(obj);
+    }
+
+    obj.map_or(std::ptr::null_mut(), |obj| obj.as_ptr().cast())
+}
+
+/// Destructs and frees [obj], which must have been created by
+/// [class_createInstance]. Always returns nil.
+///
+/// [class_createInstance]: super::class_createInstance
+#[no_mangle]
+pub extern "C" fn object_dispose(obj: id) -> id {
+    let obj = small_object::in_memory(obj)?;
+    objc_destructInstance(Some(obj));
+    unsafe { objc_object::dispose(obj.cast()) };
+    None
+}
+
+/// Returns a pointer to the storage following [obj]'s ivars, which holds the
+/// extra bytes requested when it was created.
+#[no_mangle]
+pub extern "C" fn object_getIndexedIvars(obj: id) -> *mut c_void {
+    let indexed_ivars: Option<*mut c_void> = try {
+        let obj = small_object::in_memory(obj)?;
+        let class_key = class_of(obj)?;
+        let offset =
+            CONTEXT.read().expect("poisoned rwlock").classes[class_key].indexed_ivars_offset();
+        unsafe { objc_object::ivar_ptr(obj.cast(), offset) }.cast()
+    };
+    indexed_ivars.unwrap_or(std::ptr::null_mut())
+}
+
 #[no_mangle]
 pub extern "C" fn object_getClass(obj: id) -> Class {
-    let class_key = unsafe { obj?.as_ref() };
-    NonNull::new(&mut CONTEXT.write().expect("poisoned rwlock").classes[**class_key])
+    let mut context = CONTEXT.write().expect("poisoned rwlock");
+    let class_key = context.class_of(obj?)?;
+    NonNull::new(&mut context.classes[class_key])
+}
+
+/// Sets the class of [obj] to [cls], returning its previous class. The class
+/// of a small object is fixed by its tag and a block's by how it was
+/// allocated, so nil is returned for those and their class is left alone.
+#[no_mangle]
+pub extern "C" fn object_setClass(obj: id, cls: Class) -> Class {
+    let obj = small_object::in_memory(obj)?;
+    let cls = unsafe { cls?.as_ref() };
+    let mut context = CONTEXT.write().expect("poisoned rwlock");
+    if context.block_class_of(obj).is_some() {
+        return None;
+    }
+    let old = unsafe { obj.cast::<objc_object>().as_ref() }.swap__is_a(cls.index);
+    NonNull::new(&mut context.classes[old])
+}
+
+#[no_mangle]
+pub extern "C" fn object_getClassName(obj: id) -> *const c_char {
+    class_getName(object_getClass(obj))
+}
+
+/// Creates a new instance of [obj]'s class with [extra_bytes] of indexed ivars
+/// and copies [obj]'s ivars into it, retaining what strong ivars point to and
+/// registering weak ones with their objects. Indexed ivars are not copied.
+/// Small objects are values, so they are returned as they are. Blocks must be
+/// copied with [_Block_copy] instead, so nil is returned for those.
+///
+/// [_Block_copy]: super::_Block_copy
+#[no_mangle]
+pub extern "C" fn object_copy(obj: id, extra_bytes: libc::size_t) -> id {
+    if small_object::is_small_object(obj) {
+        return obj;
+    }
+    let obj = obj?.cast::<objc_object>();
+    let (class_key, copy, ivars_size) = {
+        let context = CONTEXT.read().expect("poisoned rwlock");
+        if context.block_class_of(obj.cast()).is_some() {
+            return None;
+        }
+        let class_key = unsafe { obj.as_ref() }.is_a();
+        let class = &context.classes[class_key];
+        (
+            class_key,
+            class.create_object(extra_bytes),
+            class.indexed_ivars_offset(),
+        )
+    };
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            objc_object::ivar_ptr(obj, 0),
+            objc_object::ivar_ptr(copy, 0),
+            ivars_size,
+        )
+    };
+
+    // The copy holds references of its own, like those made by Apple's
+    // fixupCopiedIvars
+    let ivar = |obj, offset| unsafe { objc_object::ivar_ptr(obj, offset) }.cast::<id>();
+    for offset in ivar_offsets(class_key, Ownership::Strong) {
+        objc_retain(unsafe { ivar(copy, offset).read() });
+    }
+    for offset in ivar_offsets(class_key, Ownership::Weak) {
+        objc_copyWeak(ivar(copy, offset), ivar(obj, offset));
+    }
+
+    Some(copy.cast())
 }
 
 #[no_mangle]
@@ -35,16 +237,43 @@ pub extern "C" fn ivar_getOffset(ivar: Ivar) -> ptrdiff_t {
     }
 }
 
-#[no_mangle]
-pub extern "C" fn object_setIvar(obj: id, ivar: Ivar, value: id) {
+/// Stores [value] into an object ivar, retaining it or registering a weak
+/// reference according to the ivar's ownership. [default] is used for ivars
+/// whose ownership is unknown.
+fn set_ivar(obj: id, ivar: Ivar, value: id, default: Ownership) {
     let _: Option<()> = try {
+        let obj = small_object::in_memory(obj)?;
         let ivar = unsafe { ivar?.as_ref() };
-        let ivar = &mut unsafe { obj?.cast::<objc_object>().as_mut() }.ivars[ivar.offset..];
+        let location = unsafe { objc_object::ivar_ptr(obj.cast(), ivar.offset) }.cast::<id>();
 
-        unsafe { std::ptr::write(ivar.as_mut_ptr() as *mut _, value) };
+        let ownership = match ivar.ownership {
+            Ownership::Invalid => default,
+            ownership => ownership,
+        };
+        match ownership {
+            Ownership::Strong => objc_storeStrong(location, value),
+            Ownership::Weak => {
+                objc_storeWeak(location, value);
+            }
+            Ownership::Unsafe | Ownership::Invalid => unsafe { location.write(value) },
+        }
     };
 }
 
+/// Stores [value] into an object ivar. Ivars of unknown ownership are treated
+/// as `__unsafe_unretained`.
+#[no_mangle]
+pub extern "C" fn object_setIvar(obj: id, ivar: Ivar, value: id) {
+    set_ivar(obj, ivar, value, Ownership::Unsafe)
+}
+
+/// Like [object_setIvar], but ivars of unknown ownership are treated as
+/// `__strong`.
+#[no_mangle]
+pub extern "C" fn object_setIvarWithStrongDefault(obj: id, ivar: Ivar, value: id) {
+    set_ivar(obj, ivar, value, Ownership::Strong)
+}
+
 #[no_mangle]
 pub extern "C" fn object_getInstanceVariable(
     obj: id,
@@ -52,13 +281,13 @@ pub extern "C" fn object_getInstanceVariable(
     out_value: *mut *mut c_void,
 ) -> Ivar {
     let ivar: Ivar = {
-        let obj = unsafe { obj?.as_ref() };
+        let class_key = class_of(small_object::in_memory(obj)?)?;
         let name = unsafe { CStr::from_ptr(name) }
             .to_owned()
             .into_string()
             .expect("invalid utf8");
         NonNull::new(
-            CONTEXT.write().expect("poisoned rwlock").classes[**obj]
+            CONTEXT.write().expect("poisoned rwlock").classes[class_key]
                 .ivars
                 .iter_mut()
                 .find(|objc_ivar { name: name_, .. }| &name == name_)?,
@@ -82,13 +311,13 @@ pub extern "C" fn object_setInstanceVariable(
     value: *mut c_void,
 ) -> Ivar {
     let ivar: Ivar = {
-        let obj = unsafe { obj?.as_ref() };
+        let class_key = class_of(small_object::in_memory(obj)?)?;
         let name = unsafe { CStr::from_ptr(name) }
             .to_owned()
             .into_string()
             .expect("invalid utf8");
         NonNull::new(
-            CONTEXT.write().expect("poisoned rwlock").classes[**obj]
+            CONTEXT.write().expect("poisoned rwlock").classes[class_key]
                 .ivars
                 .iter_mut()
                 .find(|objc_ivar { name: name_, .. }| &name == name_)?,
@@ -0,0 +1,98 @@
+// Property lists and attribute names come from C callers, which are
+// responsible for them as with Apple's runtime.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use super::empty_string::EMPTY_STRING;
+use super::global_context::CONTEXT;
+use crate::runtime::property::{objc_property_attribute_t, Property};
+use std::{
+    ffi::{c_char, c_uint, CStr},
+    ptr::NonNull,
+};
+
+/// Builds a [Property] from a C array of [attribute_count] attributes.
+pub(crate) fn new_property(
+    name: &CStr,
+    attributes: *const objc_property_attribute_t,
+    attribute_count: c_uint,
+) -> Property {
+    let attributes = if attributes.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(attributes, attribute_count as usize) }
+    };
+
+    Property::new(
+        &mut CONTEXT.write().expect("poisoned rwlock"),
+        name.to_owned(),
+        attributes,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn property_getName(property: Option<NonNull<Property>>) -> *const c_char {
+    match property {
+        None => EMPTY_STRING.as_ptr(),
+        Some(property) => unsafe { property.as_ref() }.name.as_ptr(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn property_getAttributes(property: Option<NonNull<Property>>) -> *const c_char {
+    match property {
+        None => EMPTY_STRING.as_ptr(),
+        Some(property) => unsafe { property.as_ref() }.attributes.as_ptr(),
+    }
+}
+
+/// The returned attributes point into [property], which must outlive them.
+/// The caller owns the returned array itself.
+#[no_mangle]
+pub extern "C" fn property_copyAttributeList(
+    property: Option<NonNull<Property>>,
+    out_count: *mut c_uint,
+) -> Option<NonNull<objc_property_attribute_t>> {
+    if !out_count.is_null() {
+        unsafe { *out_count = 0 };
+    }
+
+    let attribute_list = &unsafe { property?.as_ref() }.attribute_list;
+
+    if attribute_list.is_empty() {
+        return None;
+    }
+
+    if !out_count.is_null() {
+        unsafe { *out_count = attribute_list.len() as c_uint };
+    }
+
+    NonNull::new(
+        Box::into_raw(
+            attribute_list
+                .iter()
+                .map(|attribute| objc_property_attribute_t {
+                    name: attribute.name.as_ptr(),
+                    value: attribute.value.as_ptr(),
+                })
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
+        )
+        .as_mut_ptr(),
+    )
+}
+
+/// Returns a copy of the value of the attribute named [attribute_name], which
+/// the caller must release with `free`.
+#[no_mangle]
+pub extern "C" fn property_copyAttributeValue(
+    property: Option<NonNull<Property>>,
+    attribute_name: *const c_char,
+) -> *mut c_char {
+    let value: Option<*mut c_char> = try {
+        let property = unsafe { property?.as_ref() };
+        let attribute_name = unsafe { CStr::from_ptr(attribute_name) };
+        let value = property.attribute_value(attribute_name)?;
+        unsafe { libc::strdup(value.as_ptr()) }
+    };
+    value.unwrap_or(std::ptr::null_mut())
+}
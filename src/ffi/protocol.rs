@@ -0,0 +1,222 @@
+// Called from C with names and attribute lists it vouches for, which
+// `unsafe` would say nothing more about.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use super::empty_string::EMPTY_STRING;
+use super::global_context::CONTEXT;
+use super::property::new_property;
+use crate::runtime::{
+    property::objc_property_attribute_t,
+    protocol::{objc_method_description, MethodDescription, Protocol},
+    SEL,
+};
+use std::{
+    ffi::{c_char, c_uint, CStr},
+    ptr::NonNull,
+};
+
+#[no_mangle]
+pub extern "C" fn objc_allocateProtocol(name: *const c_char) -> Option<NonNull<Protocol>> {
+    let name = unsafe { CStr::from_ptr(name) }.to_owned();
+    let mut context = CONTEXT.write().expect("poisoned rwlock");
+    context
+        .allocate_protocol(name)
+        .and_then(|protocol_key| NonNull::new(&mut context.protocols[protocol_key] as *mut _))
+}
+
+#[no_mangle]
+pub extern "C" fn objc_registerProtocol(proto: Option<NonNull<Protocol>>) {
+    if let Some(proto) = proto {
+        let proto = unsafe { proto.as_ref() };
+        CONTEXT
+            .write()
+            .expect("poisoned rwlock")
+            .register_protocol(proto.index);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn objc_getProtocol(name: *const c_char) -> Option<NonNull<Protocol>> {
+    let name = unsafe { CStr::from_ptr(name) };
+    let mut context = CONTEXT.write().expect("poisoned rwlock");
+    let protocol_key = *context.registered_protocols.get(name)?;
+    NonNull::new(&mut context.protocols[protocol_key] as *mut _)
+}
+
+#[no_mangle]
+pub extern "C" fn objc_copyProtocolList(
+    out_count: *mut c_uint,
+) -> Option<NonNull<NonNull<Protocol>>> {
+    if !out_count.is_null() {
+        unsafe { *out_count = 0 };
+    }
+
+    let mut context = CONTEXT.write().expect("poisoned rwlock");
+    let protocols = context
+        .protocols
+        .iter_mut()
+        .filter(|(_, protocol)| protocol.registered)
+        .map(|(_, protocol)| unsafe { NonNull::new_unchecked(protocol as *mut _) })
+        .collect::<Vec<_>>();
+
+    if protocols.is_empty() {
+        return None;
+    }
+
+    if !out_count.is_null() {
+        unsafe { *out_count = protocols.len() as c_uint };
+    }
+
+    NonNull::new(Box::into_raw(protocols.into_boxed_slice()).as_mut_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn protocol_getName(proto: Option<NonNull<Protocol>>) -> *const c_char {
+    match proto {
+        None => EMPTY_STRING.as_ptr(),
+        Some(proto) => unsafe { proto.as_ref() }.name.as_ptr(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn protocol_isEqual(
+    proto: Option<NonNull<Protocol>>,
+    other: Option<NonNull<Protocol>>,
+) -> bool {
+    match (proto, other) {
+        (Some(proto), Some(other)) => {
+            let (proto, other) = unsafe { (proto.as_ref(), other.as_ref()) };
+            proto.index == other.index || proto.name == other.name
+        }
+        _ => false,
+    }
+}
+
+/// Whether [proto] is [other] or inherits from it, directly or indirectly.
+#[no_mangle]
+pub extern "C" fn protocol_conformsToProtocol(
+    proto: Option<NonNull<Protocol>>,
+    other: Option<NonNull<Protocol>>,
+) -> bool {
+    match (proto, other) {
+        (Some(proto), Some(other)) => {
+            let (proto, other) = unsafe { (proto.as_ref(), other.as_ref()) };
+            CONTEXT
+                .read()
+                .expect("poisoned rwlock")
+                .protocol_conforms_to(proto.index, other.index)
+        }
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn protocol_addMethodDescription(
+    proto: Option<NonNull<Protocol>>,
+    name: SEL,
+    types: *const c_char,
+    is_required_method: bool,
+    is_instance_method: bool,
+) {
+    let _: Option<()> = try {
+        let proto = unsafe { proto?.as_mut() };
+        let name = unsafe { name?.as_ref() };
+        if proto.registered {
+            None?
+        }
+
+        let types = if types.is_null() {
+            EMPTY_STRING.to_owned()
+        } else {
+            unsafe { CStr::from_ptr(types) }.to_owned()
+        };
+
+        proto
+            .method_descriptions_mut(is_required_method, is_instance_method)
+            .push(MethodDescription {
+                selector: name.index,
+                types,
+            });
+    };
+}
+
+/// Makes [proto] inherit from [addition]. [proto] must still be under
+/// construction, and [addition] must already be registered.
+#[no_mangle]
+pub extern "C" fn protocol_addProtocol(
+    proto: Option<NonNull<Protocol>>,
+    addition: Option<NonNull<Protocol>>,
+) {
+    let _: Option<()> = try {
+        let proto = unsafe { proto?.as_mut() };
+        let addition = unsafe { addition?.as_ref() };
+        if proto.registered || !addition.registered {
+            None?
+        }
+
+        if !proto.protocols.contains(&addition.index) {
+            proto.protocols.push(addition.index);
+        }
+    };
+}
+
+/// Adds a property to [proto], which must still be under construction.
+/// Optional properties are not supported and are ignored.
+#[no_mangle]
+pub extern "C" fn protocol_addProperty(
+    proto: Option<NonNull<Protocol>>,
+    name: *const c_char,
+    attributes: *const objc_property_attribute_t,
+    attribute_count: c_uint,
+    is_required_property: bool,
+    is_instance_property: bool,
+) {
+    let _: Option<()> = try {
+        let proto = unsafe { proto?.as_mut() };
+        if proto.registered || !is_required_property {
+            None?
+        }
+
+        let name = unsafe { CStr::from_ptr(name) };
+        let property = new_property(name, attributes, attribute_count);
+        if is_instance_property {
+            proto.properties.push(property);
+        } else {
+            proto.class_properties.push(property);
+        }
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn protocol_copyMethodDescriptionList(
+    proto: Option<NonNull<Protocol>>,
+    is_required_method: bool,
+    is_instance_method: bool,
+    out_count: *mut c_uint,
+) -> Option<NonNull<objc_method_description>> {
+    if !out_count.is_null() {
+        unsafe { *out_count = 0 };
+    }
+
+    let proto = unsafe { proto?.as_ref() };
+    let descriptions = proto.method_descriptions(is_required_method, is_instance_method);
+
+    if descriptions.is_empty() {
+        return None;
+    }
+
+    let mut context = CONTEXT.write().expect("poisoned rwlock");
+    let descriptions = descriptions
+        .iter()
+        .map(|description| objc_method_description {
+            name: NonNull::new(&mut context.selectors[description.selector] as *mut _),
+            types: description.types.as_ptr(),
+        })
+        .collect::<Vec<_>>();
+
+    if !out_count.is_null() {
+        unsafe { *out_count = descriptions.len() as c_uint };
+    }
+
+    NonNull::new(Box::into_raw(descriptions.into_boxed_slice()).as_mut_ptr())
+}
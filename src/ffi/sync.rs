@@ -0,0 +1,25 @@
+use crate::runtime::{id, sync};
+use std::ffi::c_int;
+
+pub const OBJC_SYNC_SUCCESS: c_int = 0;
+pub const OBJC_SYNC_NOT_OWNING_THREAD_ERROR: c_int = -1;
+
+/// Takes the recursive lock associated with [obj], blocking until it's
+/// available. Does nothing for nil.
+#[no_mangle]
+pub extern "C" fn objc_sync_enter(obj: id) -> c_int {
+    if let Some(obj) = obj {
+        sync::enter(obj.as_ptr().addr());
+    }
+    OBJC_SYNC_SUCCESS
+}
+
+/// Releases the lock taken by [objc_sync_enter]. Fails with
+/// [OBJC_SYNC_NOT_OWNING_THREAD_ERROR] if the current thread doesn't hold it.
+#[no_mangle]
+pub extern "C" fn objc_sync_exit(obj: id) -> c_int {
+    match obj {
+        Some(obj) if !sync::exit(obj.as_ptr().addr()) => OBJC_SYNC_NOT_OWNING_THREAD_ERROR,
+        _ => OBJC_SYNC_SUCCESS,
+    }
+}
@@ -8,6 +8,15 @@
 pub mod ffi;
 mod runtime;
 
+pub use runtime::{
+    class_builder::{ClassBuilder, ClassHandle},
+    closure::{add_closure_method, IntoMethod},
+    encode::Encode,
+    id,
+    object::Object,
+    Class, SEL,
+};
+
 #[cfg(test)]
 mod tests {
 
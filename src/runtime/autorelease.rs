@@ -0,0 +1,168 @@
+use std::{
+    cell::{Cell, RefCell},
+    ffi::c_void,
+    ptr::NonNull,
+};
+
+use super::message::{id, Receiver};
+use crate::ffi::objc_release;
+
+/// Each page holds as many entries as fit in 4KiB, like Apple's
+/// `AutoreleasePoolPage`.
+const PAGE_CAPACITY: usize = 4096 / std::mem::size_of::<id>();
+
+/// A fixed-capacity run of autoreleased objects. Pages never reallocate, so
+/// the address of an entry identifies it for as long as it's in the pool.
+struct Page {
+    /// Autoreleased objects, interleaved with nil entries marking where each
+    /// pool begins.
+    entries: Vec<id>,
+}
+
+impl Page {
+    fn new() -> Self {
+        Self {
+            entries: Vec::with_capacity(PAGE_CAPACITY),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.entries.len() == PAGE_CAPACITY
+    }
+}
+
+/// The current thread's stack of pools. Pools are delimited by boundary
+/// entries, so nesting them costs nothing beyond one entry each.
+struct AutoreleasePoolStack {
+    pages: Vec<Page>,
+}
+
+impl AutoreleasePoolStack {
+    /// Adds [entry] to the hottest page, returning its address.
+    fn add(&mut self, entry: id) -> *const id {
+        if self.pages.last().is_none_or(Page::is_full) {
+            self.pages.push(Page::new());
+        }
+
+        let page = self.pages.last_mut().expect("just pushed a page");
+        page.entries.push(entry);
+        unsafe { page.entries.as_ptr().add(page.entries.len() - 1) }
+    }
+
+    /// Whether [token] is the address of a pool boundary still on the stack.
+    fn has_boundary(&self, token: *const id) -> bool {
+        self.pages.iter().rev().any(|page| {
+            page.entries.as_ptr_range().contains(&token) && {
+                let index =
+                    (token.addr() - page.entries.as_ptr().addr()) / std::mem::size_of::<id>();
+                std::ptr::eq(&page.entries[index], token) && page.entries[index].is_none()
+            }
+        })
+    }
+
+    /// Removes the most recently added entry, along with its address.
+    fn take(&mut self) -> Option<(id, *const id)> {
+        while self.pages.last()?.entries.is_empty() {
+            self.pages.pop();
+        }
+
+        let page = self.pages.last_mut()?;
+        let address = unsafe { page.entries.as_ptr().add(page.entries.len() - 1) };
+        Some((page.entries.pop()?, address))
+    }
+}
+
+impl Drop for AutoreleasePoolStack {
+    /// Drains every pool still on the stack when its thread exits.
+    fn drop(&mut self) {
+        while let Some((entry, _)) = self.take() {
+            objc_release(entry);
+        }
+    }
+}
+
+/// An object handed back by [autorelease_return_value] that its caller hasn't
+/// claimed yet. The slot owns a reference to the object.
+struct ReturnValue(Cell<id>);
+
+impl Drop for ReturnValue {
+    fn drop(&mut self) {
+        objc_release(self.0.take());
+    }
+}
+
+thread_local! {
+    static POOLS: RefCell<AutoreleasePoolStack> =
+        const { RefCell::new(AutoreleasePoolStack { pages: Vec::new() }) };
+
+    static RETURN_VALUE: ReturnValue = const { ReturnValue(Cell::new(None)) };
+}
+
+/// Starts a new pool, returning a token to pass to [pop] to end it.
+pub fn push() -> *mut c_void {
+    flush_return_value();
+    POOLS.with(|pools| pools.borrow_mut().add(None)) as *mut c_void
+}
+
+/// Releases every object autoreleased since [token] was returned by [push],
+/// most recent first. Pools pushed since then are popped as well. Tokens that
+/// aren't on this thread's stack, such as those of pools already popped, are
+/// ignored rather than draining every pool.
+pub fn pop(token: *mut c_void) {
+    if !POOLS.with(|pools| pools.borrow().has_boundary(token.cast())) {
+        return;
+    }
+
+    flush_return_value();
+    loop {
+        // Releasing may run arbitrary code, including autoreleasing more
+        // objects, so the pool must not be borrowed while we do it.
+        let Some((entry, address)) = POOLS.with(|pools| pools.borrow_mut().take()) else {
+            return;
+        };
+
+        match entry {
+            Some(obj) => objc_release(Some(obj)),
+            None if address as *mut c_void == token => return,
+            None => (),
+        }
+    }
+}
+
+/// Adds [obj] to the current thread's innermost pool. If the thread is being
+/// torn down and its pools have already been drained, [obj] is leaked.
+pub fn autorelease(obj: NonNull<Receiver>) {
+    let _ = POOLS.try_with(|pools| pools.borrow_mut().add(Some(obj)));
+}
+
+/// The callee's half of the autoreleased-return-value handshake. Rather than
+/// autoreleasing [obj], parks it in a thread-local slot; if the caller
+/// immediately claims it with [claim_return_value], the autorelease and the
+/// caller's retain cancel out and neither is performed.
+pub fn autorelease_return_value(obj: NonNull<Receiver>) {
+    flush_return_value();
+    let _ = RETURN_VALUE.try_with(|slot| slot.0.set(Some(obj)));
+}
+
+/// The caller's half of the handshake. Returns true if [obj] was the parked
+/// return value, in which case the caller now owns the reference that would
+/// have been autoreleased.
+pub fn claim_return_value(obj: NonNull<Receiver>) -> bool {
+    RETURN_VALUE
+        .try_with(|slot| match slot.0.get() {
+            Some(parked) if parked == obj => {
+                slot.0.set(None);
+                true
+            }
+            _ => false,
+        })
+        .unwrap_or(false)
+}
+
+/// Autoreleases a parked return value nobody claimed, so that it ends up in
+/// the pool it would have been in without the handshake.
+pub fn flush_return_value() {
+    if let Some(obj) = RETURN_VALUE.try_with(|slot| slot.0.take()).ok().flatten() {
+        autorelease(obj);
+    }
+}
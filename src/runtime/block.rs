@@ -0,0 +1,363 @@
+//! The blocks runtime. Blocks start out on the stack, or as constants for
+//! blocks that capture nothing; copying one moves it to the heap, where it is
+//! reference counted in its flags. Blocks are also objects: their isa points
+//! to one of the `_NSConcrete*Block` symbols, which [Context::class_of] maps
+//! to the classes registered by [register_classes].
+
+use std::{
+    ffi::{c_int, c_void, CString},
+    ptr::{addr_of, addr_of_mut, NonNull},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use super::{
+    autorelease,
+    context::{ClassKey, Context},
+    message::id,
+    method::objc_imp,
+    selector::SEL,
+};
+use crate::ffi::{
+    _NSConcreteGlobalBlock, _NSConcreteMallocBlock, _NSConcreteStackBlock, objc_release,
+    objc_retain,
+};
+
+// Flags in a block's header
+const BLOCK_DEALLOCATING: i32 = 0x0001;
+/// The reference count of a heap block, counted in twos. A count that reaches
+/// the mask sticks there and the block is never freed.
+const BLOCK_REFCOUNT_MASK: i32 = 0xfffe;
+pub const BLOCK_NEEDS_FREE: i32 = 1 << 24;
+pub const BLOCK_HAS_COPY_DISPOSE: i32 = 1 << 25;
+pub const BLOCK_IS_GLOBAL: i32 = 1 << 28;
+/// The block returns a structure through memory, whose address it's passed
+/// before anything else.
+pub const BLOCK_USE_STRET: i32 = 1 << 29;
+
+// Flags in a `__block` variable's header
+const BLOCK_BYREF_NEEDS_FREE: i32 = 1 << 24;
+const BLOCK_BYREF_HAS_COPY_DISPOSE: i32 = 1 << 25;
+const BLOCK_BYREF_LAYOUT_EXTENDED: i32 = 1 << 28;
+const BLOCK_BYREF_LAYOUT_MASK: i32 = 0xf << 28;
+
+// What a captured variable is, as passed to [assign_object] and
+// [dispose_object] by compiler-generated copy and dispose helpers
+pub const BLOCK_FIELD_IS_OBJECT: c_int = 3;
+pub const BLOCK_FIELD_IS_BLOCK: c_int = 7;
+pub const BLOCK_FIELD_IS_BYREF: c_int = 8;
+pub const BLOCK_FIELD_IS_WEAK: c_int = 16;
+const BLOCK_FIELD_IS_WEAK_BYREF: c_int = BLOCK_FIELD_IS_BYREF | BLOCK_FIELD_IS_WEAK;
+
+#[repr(C)]
+pub struct Block_layout {
+    pub isa: *const c_void,
+    pub flags: AtomicI32,
+    pub reserved: i32,
+    pub invoke: *const c_void,
+    pub descriptor: *const Block_descriptor,
+    // Captured variables follow
+}
+
+#[repr(C)]
+pub struct Block_descriptor {
+    pub reserved: usize,
+    /// Size of the whole block, captured variables included.
+    pub size: usize,
+    /// Only present if the block has [BLOCK_HAS_COPY_DISPOSE].
+    pub copy: Option<unsafe extern "C" fn(dst: *mut c_void, src: *const c_void)>,
+    pub dispose: Option<unsafe extern "C" fn(src: *const c_void)>,
+}
+
+/// The storage of a `__block` variable, which starts out on the stack and is
+/// moved to the heap along with the first block capturing it.
+#[repr(C)]
+struct Block_byref {
+    isa: *const c_void,
+    /// The copy of this variable everyone should use: itself until it has
+    /// been moved to the heap.
+    forwarding: *mut Block_byref,
+    flags: AtomicI32,
+    size: u32,
+    /// Only present if the variable has [BLOCK_BYREF_HAS_COPY_DISPOSE].
+    keep: unsafe extern "C" fn(dst: *mut Block_byref, src: *mut Block_byref),
+    destroy: unsafe extern "C" fn(*mut Block_byref),
+    /// Only present if the variable has [BLOCK_BYREF_LAYOUT_EXTENDED].
+    layout: *const c_void,
+}
+
+/// Size of a [Block_byref]'s header, before the optional fields.
+const BYREF_HEADER_SIZE: usize = std::mem::offset_of!(Block_byref, keep);
+
+fn latching_incr(flags: &AtomicI32) {
+    let _ = flags.fetch_update(Ordering::AcqRel, Ordering::Relaxed, |old| {
+        (old & BLOCK_REFCOUNT_MASK != BLOCK_REFCOUNT_MASK).then_some(old + 2)
+    });
+}
+
+/// Drops a reference, returning true if it was the last one. The last
+/// reference marks the block as deallocating instead of reaching zero.
+fn latching_decr_should_deallocate(flags: &AtomicI32) -> bool {
+    flags
+        .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |old| {
+            match old & BLOCK_REFCOUNT_MASK {
+                BLOCK_REFCOUNT_MASK | 0 => None,
+                _ if old & (BLOCK_REFCOUNT_MASK | BLOCK_DEALLOCATING) == 2 => Some(old - 1),
+                _ => Some(old - 2),
+            }
+        })
+        .is_ok_and(|old| old & (BLOCK_REFCOUNT_MASK | BLOCK_DEALLOCATING) == 2)
+}
+
+/// Copies a stack block to the heap, or takes another reference to a block
+/// already there. Global blocks are returned as they are.
+///
+/// # Safety
+///
+/// [block] must be null or a valid block.
+pub unsafe fn copy(block: *mut Block_layout) -> *mut Block_layout {
+    let Some(block_ref) = block.as_ref() else {
+        return block;
+    };
+    let flags = block_ref.flags.load(Ordering::Relaxed);
+    if flags & BLOCK_NEEDS_FREE != 0 {
+        latching_incr(&block_ref.flags);
+        return block;
+    }
+    if flags & BLOCK_IS_GLOBAL != 0 {
+        return block;
+    }
+
+    let size = (*block_ref.descriptor).size;
+    let result = libc::malloc(size).cast::<Block_layout>();
+    assert!(!result.is_null(), "out of memory copying a block");
+    std::ptr::copy_nonoverlapping(block.cast::<u8>(), result.cast::<u8>(), size);
+
+    // The copy starts with a single reference
+    let flags = flags & !(BLOCK_REFCOUNT_MASK | BLOCK_DEALLOCATING) | BLOCK_NEEDS_FREE | 2;
+    (*result).flags = AtomicI32::new(flags);
+    if flags & BLOCK_HAS_COPY_DISPOSE != 0 {
+        if let Some(copy_helper) = addr_of!((*block_ref.descriptor).copy).read() {
+            copy_helper(result.cast(), block.cast());
+        }
+    }
+    (*result).isa = addr_of!(_NSConcreteMallocBlock).cast();
+    result
+}
+
+/// Drops a reference to a heap block, freeing it and releasing what it
+/// captured when it was the last. Stack and global blocks are left alone.
+///
+/// # Safety
+///
+/// [block] must be null or a valid block.
+pub unsafe fn release(block: *mut Block_layout) {
+    let Some(block_ref) = block.as_ref() else {
+        return;
+    };
+    let flags = block_ref.flags.load(Ordering::Relaxed);
+    if flags & BLOCK_IS_GLOBAL != 0 || flags & BLOCK_NEEDS_FREE == 0 {
+        return;
+    }
+
+    if latching_decr_should_deallocate(&block_ref.flags) {
+        if flags & BLOCK_HAS_COPY_DISPOSE != 0 {
+            if let Some(dispose_helper) = addr_of!((*block_ref.descriptor).dispose).read() {
+                dispose_helper(block.cast());
+            }
+        }
+        libc::free(block.cast());
+    }
+}
+
+/// Moves a `__block` variable to the heap the first time a block capturing
+/// it is copied, and takes another reference to it afterwards. Returns the
+/// heap copy.
+unsafe fn byref_copy(byref: *mut Block_byref) -> *mut Block_byref {
+    let forwarding = (*byref).forwarding;
+    let flags = (*forwarding).flags.load(Ordering::Relaxed);
+
+    if flags & BLOCK_REFCOUNT_MASK == 0 {
+        let size = (*byref).size as usize;
+        let copy = libc::malloc(size).cast::<Block_byref>();
+        assert!(!copy.is_null(), "out of memory copying a __block variable");
+
+        addr_of_mut!((*copy).isa).write(std::ptr::null());
+        // One reference from the stack and one from the heap
+        addr_of_mut!((*copy).flags).write(AtomicI32::new(flags | BLOCK_BYREF_NEEDS_FREE | 4));
+        addr_of_mut!((*copy).forwarding).write(copy);
+        addr_of_mut!((*copy).size).write((*byref).size);
+        (*byref).forwarding = copy;
+
+        if flags & BLOCK_BYREF_HAS_COPY_DISPOSE != 0 {
+            // The variable may be an object or C++ value that can't just be
+            // copied bytewise; its keep helper knows how.
+            addr_of_mut!((*copy).keep).write(addr_of!((*byref).keep).read());
+            addr_of_mut!((*copy).destroy).write(addr_of!((*byref).destroy).read());
+            if flags & BLOCK_BYREF_LAYOUT_MASK == BLOCK_BYREF_LAYOUT_EXTENDED {
+                addr_of_mut!((*copy).layout).write(addr_of!((*byref).layout).read());
+            }
+            (addr_of!((*byref).keep).read())(copy, byref);
+        } else {
+            std::ptr::copy_nonoverlapping(
+                byref.cast::<u8>().add(BYREF_HEADER_SIZE),
+                copy.cast::<u8>().add(BYREF_HEADER_SIZE),
+                size - BYREF_HEADER_SIZE,
+            );
+        }
+    } else if flags & BLOCK_BYREF_NEEDS_FREE != 0 {
+        latching_incr(&(*forwarding).flags);
+    }
+
+    (*byref).forwarding
+}
+
+unsafe fn byref_release(byref: *mut Block_byref) {
+    let byref = (*byref).forwarding;
+    let flags = (*byref).flags.load(Ordering::Relaxed);
+    if flags & BLOCK_BYREF_NEEDS_FREE == 0 {
+        return;
+    }
+
+    if latching_decr_should_deallocate(&(*byref).flags) {
+        if flags & BLOCK_BYREF_HAS_COPY_DISPOSE != 0 {
+            (addr_of!((*byref).destroy).read())(byref);
+        }
+        libc::free(byref.cast());
+    }
+}
+
+/// Copies the captured variable [object] into [dest], as a block's copy
+/// helper does for every capture that needs more than a bytewise copy.
+///
+/// # Safety
+///
+/// [dest] must be valid for writes, and [object] must be what [flags] says.
+pub unsafe fn assign_object(dest: *mut *mut c_void, object: *mut c_void, flags: c_int) {
+    *dest = match flags {
+        BLOCK_FIELD_IS_OBJECT => {
+            objc_retain(NonNull::new(object.cast()));
+            object
+        }
+        BLOCK_FIELD_IS_BLOCK => copy(object.cast()).cast(),
+        BLOCK_FIELD_IS_BYREF | BLOCK_FIELD_IS_WEAK_BYREF => byref_copy(object.cast()).cast(),
+        // Called by a `__block` variable's helpers (`BLOCK_BYREF_CALLER`),
+        // which manage the variable's object themselves
+        _ => object,
+    };
+}
+
+/// Releases the captured variable [object], as a block's dispose helper does
+/// for everything [assign_object] copied.
+///
+/// # Safety
+///
+/// [object] must be what [flags] says.
+pub unsafe fn dispose_object(object: *mut c_void, flags: c_int) {
+    match flags {
+        BLOCK_FIELD_IS_OBJECT => objc_release(NonNull::new(object.cast())),
+        BLOCK_FIELD_IS_BLOCK => release(object.cast()),
+        BLOCK_FIELD_IS_BYREF | BLOCK_FIELD_IS_WEAK_BYREF => byref_release(object.cast()),
+        _ => (),
+    }
+}
+
+unsafe extern "C" fn block_copy(self_: id, _cmd: SEL, _: ...) -> id {
+    NonNull::new(copy(self_?.as_ptr().cast()).cast())
+}
+
+unsafe extern "C" fn block_retain(self_: id, _cmd: SEL, _: ...) -> id {
+    self_
+}
+
+unsafe extern "C" fn block_release(_self: id, _cmd: SEL, _: ...) -> id {
+    None
+}
+
+unsafe extern "C" fn block_retain_count(_self: id, _cmd: SEL, _: ...) -> id {
+    NonNull::new(std::ptr::without_provenance_mut(1))
+}
+
+unsafe extern "C" fn malloc_block_release(self_: id, _cmd: SEL, _: ...) -> id {
+    release(self_?.as_ptr().cast());
+    None
+}
+
+unsafe extern "C" fn malloc_block_autorelease(self_: id, _cmd: SEL, _: ...) -> id {
+    autorelease::autorelease(self_?);
+    self_
+}
+
+unsafe extern "C" fn malloc_block_retain_count(self_: id, _cmd: SEL, _: ...) -> id {
+    let flags = (*self_?.as_ptr().cast::<Block_layout>())
+        .flags
+        .load(Ordering::Relaxed);
+    NonNull::new(std::ptr::without_provenance_mut(
+        (flags & BLOCK_REFCOUNT_MASK) as usize / 2,
+    ))
+}
+
+fn add_method(context: &mut Context, class_key: ClassKey, name: &str, imp: objc_imp, types: &str) {
+    let selector = context.allocate_selector(CString::new(name).expect("valid name"));
+    context.classes[class_key].add_method(&context.selectors[selector], imp, types.to_owned());
+}
+
+/// Registers `NSBlock` and its concrete subclasses, one for each kind of block.
+/// Stack and global blocks aren't reference counted; copying a stack block
+/// moves it to the heap.
+pub fn register_classes(context: &mut Context) {
+    let allocate = |context: &mut Context, superclass, name: &str| {
+        let name = CString::new(name).expect("valid name");
+        let class_key = context
+            .allocate_class_pair(superclass, name, 0)
+            .expect("block classes registered twice");
+        context.register_class_pair(class_key);
+        class_key
+    };
+
+    let ns_block = allocate(context, None, "NSBlock");
+    add_method(context, ns_block, "copy", block_copy, "@16@0:8");
+    add_method(context, ns_block, "retain", block_retain, "@16@0:8");
+    add_method(context, ns_block, "release", block_release, "v16@0:8");
+    add_method(context, ns_block, "autorelease", block_retain, "@16@0:8");
+    add_method(
+        context,
+        ns_block,
+        "retainCount",
+        block_retain_count,
+        "Q16@0:8",
+    );
+
+    let malloc_block = allocate(context, Some(ns_block), "__NSMallocBlock__");
+    add_method(context, malloc_block, "retain", block_copy, "@16@0:8");
+    add_method(
+        context,
+        malloc_block,
+        "release",
+        malloc_block_release,
+        "v16@0:8",
+    );
+    add_method(
+        context,
+        malloc_block,
+        "autorelease",
+        malloc_block_autorelease,
+        "@16@0:8",
+    );
+    add_method(
+        context,
+        malloc_block,
+        "retainCount",
+        malloc_block_retain_count,
+        "Q16@0:8",
+    );
+
+    let stack_block = allocate(context, Some(ns_block), "__NSStackBlock__");
+    let global_block = allocate(context, Some(ns_block), "__NSGlobalBlock__");
+
+    context.block_classes = [
+        (addr_of!(_NSConcreteStackBlock).addr(), stack_block),
+        (addr_of!(_NSConcreteMallocBlock).addr(), malloc_block),
+        (addr_of!(_NSConcreteGlobalBlock).addr(), global_block),
+    ]
+    .into();
+}
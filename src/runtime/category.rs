@@ -0,0 +1,33 @@
+use std::ffi::CString;
+
+use super::{context::ProtocolKey, method::objc_method, property::Property};
+
+/// A bundle of methods, protocols and properties to be attached to an existing
+/// class. Categories are consumed when they are attached; if the class they
+/// name has not been registered yet, they are held by the [Context] until it is.
+///
+/// [Context]: super::context::Context
+#[allow(non_camel_case_types)]
+pub struct objc_category {
+    pub(crate) name: CString,
+    pub(crate) class_name: CString,
+    pub(crate) instance_methods: Vec<objc_method>,
+    pub(crate) class_methods: Vec<objc_method>,
+    pub(crate) protocols: Vec<ProtocolKey>,
+    pub(crate) properties: Vec<Property>,
+}
+
+impl objc_category {
+    pub fn new(class_name: CString, name: CString) -> Self {
+        Self {
+            name,
+            class_name,
+            instance_methods: Vec::new(),
+            class_methods: Vec::new(),
+            protocols: Vec::new(),
+            properties: Vec::new(),
+        }
+    }
+}
+
+pub type Category = Option<std::ptr::NonNull<objc_category>>;
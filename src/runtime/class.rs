@@ -1,13 +1,19 @@
-use std::{alloc::Layout, ffi::CString, ptr::NonNull};
+use std::{
+    alloc::Layout,
+    collections::{BTreeSet, HashMap},
+    ffi::CString,
+    ptr::NonNull,
+    sync::atomic::AtomicU8,
+};
 
 use super::{
-    context::ClassKey,
-    ivar::objc_ivar,
+    context::{ClassKey, ProtocolKey, SelectorKey},
+    ivar::{encode_layout, objc_ivar, Ownership},
     message::Repr,
-    method::objc_method,
+    method::{objc_imp, objc_method},
     object::{objc_object, ObjectData},
     property::Property,
-    protocol::Protocol,
+    selector::objc_selector,
 };
 
 bitflags::bitflags! {
@@ -23,15 +29,33 @@ impl Default for Flags {
     }
 }
 
+pub(crate) const CUSTOM_RR_UNKNOWN: u8 = 0;
+pub(crate) const CUSTOM_RR_NO: u8 = 1;
+pub(crate) const CUSTOM_RR_YES: u8 = 2;
+
 /// cbindgen:ignore
 #[derive(Default)]
 pub struct ClassData {
     pub superclass: Option<ClassKey>,
-    // TODO: this should be not an i8
-    // dispatch_table: i8,
+    /// Cache of resolved implementations, including inherited ones. Flushed
+    /// by [Context::flush_caches] whenever a method list changes.
+    ///
+    /// [Context::flush_caches]: super::context::Context::flush_caches
+    pub(crate) dispatch_table: HashMap<SelectorKey, objc_imp>,
+    /// Whether this class or a superclass implements its own reference
+    /// counting methods; one of the `CUSTOM_RR_*` constants. Computed on
+    /// demand by [Context::has_custom_rr] and reset along with
+    /// [ClassData::dispatch_table].
+    ///
+    /// [Context::has_custom_rr]: super::context::Context::has_custom_rr
+    pub(crate) custom_rr: AtomicU8,
     // first_subclass: Arc<Class>,
-    // cxx_construct: Option<Imp>,
-    // cxx_destruct: Option<Imp>,
+    /// Initializes the C++ and ARC ivars declared by this class (but not its
+    /// superclasses). Run from the root class down when an instance is created.
+    pub(crate) cxx_construct: Option<objc_imp>,
+    /// Tears down what [ClassData::cxx_construct] set up. Run from the leaf
+    /// class up when an instance is destroyed.
+    pub(crate) cxx_destruct: Option<objc_imp>,
     // first_sibling: Box<Class>,
     /// We use a [CString] because in [class_getName] we need to present a
     /// C-compatible (null-terminated) string and we need somewhere to store the
@@ -40,12 +64,17 @@ pub struct ClassData {
     pub(crate) index: ClassKey,
     pub ivars: Vec<objc_ivar>,
     pub methods: Vec<objc_method>,
-    pub protocols: Vec<Protocol>,
+    pub protocols: Vec<ProtocolKey>,
     // TODO: this should be not an i8
     pub reference_list: i8,
     pub properties: Vec<Property>,
     pub info: Flags,
     pub(crate) ivar_layout: Option<std::alloc::Layout>,
+    /// Cached bitmaps of the words holding strong and weak object pointers,
+    /// as returned by [ClassData::layout_bitmap]. Reset whenever an ivar's
+    /// ownership could have changed.
+    pub(crate) strong_layout_bitmap: Option<Box<[u8]>>,
+    pub(crate) weak_layout_bitmap: Option<Box<[u8]>>,
     pub(crate) extra_bytes: usize,
 }
 
@@ -109,45 +138,113 @@ impl objc_class {
         self.ivar_layout = Some(ivar_layout);
 
         self.ivars.push(ivar);
+        self.strong_layout_bitmap = None;
+        self.weak_layout_bitmap = None;
         true
     }
 
-    pub(crate) fn instance_layout(&self) -> Layout {
-        let extra_bytes_layout =
-            Layout::from_size_align(self.extra_bytes, std::mem::align_of::<u8>())
-                .expect("invalid size/align");
-        let dtable_layout = match self.ivar_layout {
-            Some(ivar_layout) => {
-                let (layout, _extra_bytes_offset) = ivar_layout
-                    .extend(extra_bytes_layout)
-                    .expect("invalid layout extension");
-                // TODO: store the extra bytes offset
-                layout
+    /// Index of the instance word [ivar] occupies, if it is exactly one
+    /// word-aligned word.
+    fn ivar_word(ivar: &objc_ivar) -> Option<usize> {
+        let word_size = std::mem::size_of::<usize>();
+        let offset = memoffset::offset_of!(Repr<ObjectData>, data) + ivar.offset;
+        (ivar.size == word_size && offset.is_multiple_of(word_size)).then_some(offset / word_size)
+    }
+
+    fn layout_bitmap_mut(&mut self, ownership: Ownership) -> &mut Option<Box<[u8]>> {
+        match ownership {
+            Ownership::Strong => &mut self.strong_layout_bitmap,
+            Ownership::Weak => &mut self.weak_layout_bitmap,
+            _ => panic!("no layout bitmap for {ownership:?} ivars"),
+        }
+    }
+
+    /// Bitmap of the words of an instance that hold [ownership] (strong or
+    /// weak) object pointers. Object ivars start out strong (see
+    /// [objc_ivar::new]), so without an explicit layout this is derived from
+    /// their type encodings.
+    pub(crate) fn layout_bitmap(&mut self, ownership: Ownership) -> &[u8] {
+        if self.layout_bitmap_mut(ownership).is_none() {
+            let words = self
+                .ivars
+                .iter()
+                .filter(|ivar| ivar.ownership == ownership)
+                .filter_map(Self::ivar_word)
+                .collect::<BTreeSet<_>>();
+            *self.layout_bitmap_mut(ownership) = Some(encode_layout(&words));
+        }
+
+        self.layout_bitmap_mut(ownership)
+            .as_deref()
+            .expect("bitmap was just computed")
+    }
+
+    /// Gives the ivars in [words] [ownership] (strong or weak). Ivars that had
+    /// that ownership but aren't in [words] become unretained.
+    pub(crate) fn set_layout_bitmap(&mut self, ownership: Ownership, words: &BTreeSet<usize>) {
+        for ivar in &mut self.ivars {
+            let in_layout = Self::ivar_word(ivar).is_some_and(|word| words.contains(&word));
+            if in_layout {
+                ivar.ownership = ownership;
+            } else if ivar.ownership == ownership {
+                ivar.ownership = Ownership::Unsafe;
             }
-            None => extra_bytes_layout,
-        };
+        }
+
+        self.strong_layout_bitmap = None;
+        self.weak_layout_bitmap = None;
+    }
+
+    pub fn add_method(&mut self, selector: &objc_selector, imp: objc_imp, types: String) {
+        self.note_cxx_method(selector, imp);
+        self.methods.push(objc_method::new(imp, selector, types));
+    }
+
+    /// Records [imp] as [ClassData::cxx_construct] or [ClassData::cxx_destruct]
+    /// if [selector] names one and the class doesn't have one yet.
+    pub(crate) fn note_cxx_method(&mut self, selector: &objc_selector, imp: objc_imp) {
+        match selector.selector_info.name.as_bytes() {
+            b".cxx_construct" => self.cxx_construct = self.cxx_construct.or(Some(imp)),
+            b".cxx_destruct" => self.cxx_destruct = self.cxx_destruct.or(Some(imp)),
+            _ => (),
+        }
+    }
+
+    /// Layout of an instance's ivars followed by its indexed ivars: the
+    /// class' [ClassData::extra_bytes] plus [extra_bytes] more for this
+    /// instance. Also returns the offset of the indexed ivars within it.
+    fn ivars_layout(&self, extra_bytes: usize) -> (Layout, usize) {
+        let extra_bytes_layout = Layout::from_size_align(
+            self.extra_bytes + extra_bytes,
+            std::mem::align_of::<usize>(),
+        )
+        .expect("invalid size/align");
+        match self.ivar_layout {
+            Some(ivar_layout) => ivar_layout
+                .extend(extra_bytes_layout)
+                .expect("invalid layout extension"),
+            None => (extra_bytes_layout, 0),
+        }
+    }
+
+    /// Offset of the indexed ivars from the start of an instance's ivars.
+    pub(crate) fn indexed_ivars_offset(&self) -> usize {
+        self.ivars_layout(0).1
+    }
+
+    pub(crate) fn instance_layout(&self) -> Layout {
+        let (ivars_layout, _) = self.ivars_layout(0);
         let (layout, _dt_offset) = Layout::new::<Repr<ObjectData>>()
-            .extend(dtable_layout)
+            .extend(ivars_layout)
             .expect("bad layout I guess");
         layout
     }
 
-    pub fn create_object(&self) -> NonNull<objc_object> {
-        // TODO: use [instance_layout] here
-        let extra_bytes_layout =
-            Layout::from_size_align(self.extra_bytes, std::mem::align_of::<u8>())
-                .expect("invalid size/align");
-        let dtable_layout = match self.ivar_layout {
-            Some(ivar_layout) => {
-                let (layout, _extra_bytes_offset) = ivar_layout
-                    .extend(extra_bytes_layout)
-                    .expect("invalid layout extension");
-                // TODO: store the extra bytes offset
-                layout
-            }
-            None => extra_bytes_layout,
-        };
-        objc_object::new(self.is_a(), dtable_layout)
+    /// Allocates an instance with [extra_bytes] bytes of indexed ivars on top of
+    /// those every instance of this class has.
+    pub fn create_object(&self, extra_bytes: usize) -> NonNull<objc_object> {
+        let (ivars_layout, _) = self.ivars_layout(extra_bytes);
+        objc_object::new(self.index, ivars_layout)
     }
 }
 
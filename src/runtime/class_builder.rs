@@ -0,0 +1,136 @@
+//! A safe way to build classes from Rust, instead of going through
+//! `objc_allocateClassPair` and friends with C strings and raw pointers.
+//! Classes are referred to by key and looked up under the runtime's lock
+//! each time, since the classes themselves move as more are allocated.
+
+use std::ffi::CString;
+
+use super::{
+    class::Class,
+    closure::{add_closure_method_to, IntoMethod},
+    context::ClassKey,
+    encode::Encode,
+    ivar::objc_ivar,
+    message::id,
+};
+use crate::ffi::{class_createInstance, global_context::CONTEXT, imp_removeBlock};
+
+/// A registered class. Classes are never freed, so handles can be copied and
+/// shared freely.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ClassHandle(ClassKey);
+
+impl ClassHandle {
+    /// The registered class named [name].
+    pub fn lookup(name: &str) -> Option<Self> {
+        let name = CString::new(name).ok()?;
+        let context = CONTEXT.read().expect("poisoned rwlock");
+        context.registered_classes.get(&name).copied().map(Self)
+    }
+
+    pub fn name(&self) -> CString {
+        CONTEXT.read().expect("poisoned rwlock").classes[self.0]
+            .name
+            .clone()
+    }
+
+    /// The class, for passing to the runtime's C functions.
+    pub fn as_class(&self) -> Class {
+        let mut context = CONTEXT.write().expect("poisoned rwlock");
+        Some((&mut context.classes[self.0]).into())
+    }
+
+    /// Creates an instance of the class, which the caller owns a reference to.
+    pub fn create_instance(&self) -> id {
+        class_createInstance(self.as_class(), 0)
+    }
+}
+
+/// A class being built. It can't be instantiated until [ClassBuilder::register]
+/// is called, and is thrown away if the builder is dropped before then.
+pub struct ClassBuilder(ClassKey);
+
+impl ClassBuilder {
+    /// Starts a class named [name], inheriting from [superclass] or a root
+    /// class if it's [None]. Returns [None] if a class with that name is
+    /// already registered.
+    pub fn new(name: &str, superclass: Option<ClassHandle>) -> Option<Self> {
+        let name = CString::new(name).ok()?;
+        let superclass = superclass.map(|superclass| superclass.0);
+        CONTEXT
+            .write()
+            .expect("poisoned rwlock")
+            .allocate_class_pair(superclass, name, 0)
+            .map(Self)
+    }
+
+    /// Adds an ivar holding a [T], aligned as [T] requires. Returns false if
+    /// the class already has an ivar named [name].
+    pub fn add_ivar<T: Encode>(&mut self, name: &str) -> bool {
+        let ivar = objc_ivar::new(
+            name.to_owned(),
+            std::mem::size_of::<T>(),
+            std::mem::align_of::<T>().trailing_zeros() as u8,
+            T::ENCODING.to_owned(),
+        );
+        CONTEXT.write().expect("poisoned rwlock").classes[self.0].add_ivar(ivar)
+    }
+
+    /// Adds [closure] as the method named [name], with a type encoding
+    /// derived from its signature. The closure must not panic; see
+    /// [IntoMethod].
+    pub fn add_method<Args, Ret, F: IntoMethod<Args, Ret>>(
+        &mut self,
+        name: &str,
+        closure: F,
+    ) -> bool {
+        let Ok(name) = CString::new(name) else {
+            return false;
+        };
+        add_closure_method_to(self.0, &name, closure)
+    }
+
+    /// Adopts the registered protocol named [name]. Returns false if there is
+    /// no such protocol or the class already conforms to it.
+    pub fn add_protocol(&mut self, name: &str) -> bool {
+        let Ok(name) = CString::new(name) else {
+            return false;
+        };
+        let mut context = CONTEXT.write().expect("poisoned rwlock");
+        let Some(&protocol_key) = context.registered_protocols.get(&name) else {
+            return false;
+        };
+        if context.class_conforms_to(self.0, protocol_key) {
+            return false;
+        }
+        context.classes[self.0].protocols.push(protocol_key);
+        true
+    }
+
+    /// Registers the class, after which it can be instantiated.
+    pub fn register(self) -> ClassHandle {
+        let class_key = self.0;
+        std::mem::forget(self);
+        CONTEXT
+            .write()
+            .expect("poisoned rwlock")
+            .register_class_pair(class_key);
+        ClassHandle(class_key)
+    }
+}
+
+impl Drop for ClassBuilder {
+    fn drop(&mut self) {
+        let classes = CONTEXT
+            .write()
+            .expect("poisoned rwlock")
+            .dispose_class_pair(self.0);
+
+        // Closures are dropped with their blocks, and could do anything
+        for class in &classes {
+            for method in &class.methods {
+                imp_removeBlock(Some(method.imp));
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Methods implemented by Rust closures. A closure is wrapped in a block whose
+//! invoke function is generated for the closure's exact signature, then
+//! turned into an implementation with [imp_implementationWithBlock], so the
+//! arguments arrive exactly as the caller passed them.
+
+use std::{
+    ffi::{c_void, CStr, CString},
+    mem::ManuallyDrop,
+    ptr::{addr_of, addr_of_mut, NonNull},
+    sync::atomic::AtomicI32,
+};
+
+use super::{
+    block::{Block_descriptor, Block_layout, BLOCK_HAS_COPY_DISPOSE},
+    class::Class,
+    context::ClassKey,
+    encode::Encode,
+    message::id,
+    object::Object,
+    selector::SEL,
+    IMP,
+};
+use crate::ffi::{
+    _NSConcreteStackBlock, global_context::CONTEXT, imp_implementationWithBlock, imp_removeBlock,
+};
+
+#[repr(C)]
+struct ClosureBlock<F> {
+    layout: Block_layout,
+    closure: F,
+}
+
+impl<F> ClosureBlock<F> {
+    /// Copying the block moves the closure bytewise, which is all a Rust move
+    /// is, so only disposing of it needs a helper.
+    const DESCRIPTOR: Block_descriptor = Block_descriptor {
+        reserved: 0,
+        size: std::mem::size_of::<Self>(),
+        copy: None,
+        dispose: Some(Self::dispose),
+    };
+
+    unsafe extern "C" fn dispose(block: *const c_void) {
+        let block = block.cast::<Self>().cast_mut();
+        std::ptr::drop_in_place(addr_of_mut!((*block).closure));
+    }
+
+    /// An implementation calling [closure] through [invoke], which must take
+    /// a `*mut ClosureBlock<F>` followed by the method's arguments.
+    fn imp(closure: F, invoke: *const c_void) -> IMP {
+        // Blocks are copied with malloc, which doesn't align for anything
+        // stricter than this
+        const { assert!(std::mem::align_of::<F>() <= std::mem::align_of::<libc::max_align_t>()) };
+
+        // The copy made by [imp_implementationWithBlock] owns the closure
+        // from then on, and drops it when the implementation is removed.
+        let mut block = ManuallyDrop::new(Self {
+            layout: Block_layout {
+                isa: addr_of!(_NSConcreteStackBlock).cast(),
+                flags: AtomicI32::new(BLOCK_HAS_COPY_DISPOSE),
+                reserved: 0,
+                invoke,
+                descriptor: &Self::DESCRIPTOR,
+            },
+            closure,
+        });
+        imp_implementationWithBlock(NonNull::new(addr_of_mut!(*block).cast()))
+    }
+}
+
+/// A closure that can implement a method: one taking the receiver followed by
+/// the method's arguments, excluding the selector. [Args] is the tuple of
+/// argument types.
+///
+/// The closure is called from C, which Rust panics can't unwind through, so a
+/// panic in it aborts the process. So does calling the method directly
+/// through its implementation with a nil receiver.
+pub trait IntoMethod<Args, Ret> {
+    /// The method's type encoding.
+    fn types() -> CString;
+
+    /// An implementation calling the closure, which lives until the
+    /// implementation is passed to [imp_removeBlock]. [None] if no more
+    /// trampolines could be allocated.
+    fn into_imp(self) -> IMP;
+}
+
+macro_rules! into_method {
+    ($($arg:ident $value:ident),*) => {
+        impl<F, Ret, $($arg),*> IntoMethod<($($arg,)*), Ret> for F
+        where
+            F: Fn(&Object, $($arg),*) -> Ret + Send + Sync + 'static,
+            Ret: Encode,
+            $($arg: Encode,)*
+        {
+            fn types() -> CString {
+                let types = [Ret::ENCODING, id::ENCODING, SEL::ENCODING $(, $arg::ENCODING)*];
+                CString::new(types.concat()).expect("encodings have no nul bytes")
+            }
+
+            fn into_imp(self) -> IMP {
+                unsafe extern "C" fn invoke<F, Ret, $($arg),*>(
+                    block: *mut ClosureBlock<F>,
+                    self_: id,
+                    $($value: $arg),*
+                ) -> Ret
+                where
+                    F: Fn(&Object, $($arg),*) -> Ret,
+                {
+                    let receiver = self_.expect("method implemented by a closure sent to nil");
+                    ((*block).closure)(receiver.cast::<Object>().as_ref(), $($value),*)
+                }
+
+                ClosureBlock::imp(self, invoke::<F, Ret, $($arg),*> as *const c_void)
+            }
+        }
+    };
+}
+
+into_method!();
+into_method!(A a);
+into_method!(A a, B b);
+into_method!(A a, B b, C c);
+into_method!(A a, B b, C c, D d);
+into_method!(A a, B b, C c, D d, E e);
+into_method!(A a, B b, C c, D d, E e, G g);
+
+/// Adds [closure] to [cls] as the method named [name], with a type encoding
+/// derived from the closure's signature. Returns false if the method couldn't
+/// be added. The closure must not panic; see [IntoMethod].
+///
+/// # Safety
+///
+/// [cls] must be nil or a class returned by the runtime. [ClassBuilder] adds
+/// methods without this requirement.
+///
+/// [ClassBuilder]: super::class_builder::ClassBuilder
+pub unsafe fn add_closure_method<Args, Ret, F: IntoMethod<Args, Ret>>(
+    cls: Class,
+    name: &CStr,
+    closure: F,
+) -> bool {
+    match cls {
+        Some(cls) => add_closure_method_to(cls.as_ref().index, name, closure),
+        None => false,
+    }
+}
+
+/// Like [add_closure_method], but for the class [class_key].
+pub(crate) fn add_closure_method_to<Args, Ret, F: IntoMethod<Args, Ret>>(
+    class_key: ClassKey,
+    name: &CStr,
+    closure: F,
+) -> bool {
+    let Some(imp) = closure.into_imp() else {
+        return false;
+    };
+    let types = F::types().into_string().expect("encodings are ASCII");
+
+    let added = {
+        let mut context = CONTEXT.write().expect("poisoned rwlock");
+        let context = &mut *context;
+        let selector_key = context.allocate_selector(name.to_owned());
+        match context.classes.get_mut(class_key) {
+            Some(class) => {
+                class.add_method(&context.selectors[selector_key], imp, types);
+                context.flush_caches();
+                true
+            }
+            None => false,
+        }
+    };
+    if !added {
+        imp_removeBlock(Some(imp));
+    }
+    added
+}
@@ -8,18 +8,38 @@ new_key_type! {
     pub struct SelectorKey;
 }
 
+new_key_type! {
+    pub struct ProtocolKey;
+}
+
 use super::{
-    class::{objc_class, ClassData, Flags},
+    category::objc_category,
+    class::{objc_class, ClassData, Flags, CUSTOM_RR_NO, CUSTOM_RR_UNKNOWN, CUSTOM_RR_YES},
+    message::Receiver,
+    method::objc_imp,
+    protocol::Protocol,
     selector::{objc_selector, SelectorInfo},
+    small_object::{self, SMALL_OBJECT_MASK},
 };
-use std::{collections::HashMap, ffi::CString};
+use std::{collections::HashMap, ffi::CString, ptr::NonNull, sync::atomic::Ordering};
 
 pub struct Context {
     pub(crate) classes: SlotMap<ClassKey, objc_class>,
     pub(crate) selectors: SlotMap<SelectorKey, objc_selector>,
+    pub(crate) protocols: SlotMap<ProtocolKey, Protocol>,
     pub(crate) registered_classes: HashMap<CString, ClassKey>,
     pub(crate) registered_metaclasses: HashMap<CString, ClassKey>,
     pub(crate) selectors_by_name: HashMap<SelectorInfo, SelectorKey>,
+    pub(crate) registered_protocols: HashMap<CString, ProtocolKey>,
+    /// Categories whose target class has not been registered yet, keyed by the
+    /// target class' name.
+    pub(crate) pending_categories: HashMap<CString, Vec<objc_category>>,
+    /// Classes of small objects, indexed by tag. Tag 0 marks real object
+    /// pointers, so its slot is always empty.
+    pub(crate) small_object_classes: [Option<ClassKey>; SMALL_OBJECT_MASK + 1],
+    /// Classes of blocks, keyed by the address of the `_NSConcrete*Block`
+    /// symbol their isa points to.
+    pub(crate) block_classes: Vec<(usize, ClassKey)>,
 }
 
 impl Context {
@@ -27,9 +47,14 @@ impl Context {
         Self {
             classes: SlotMap::with_key(),
             selectors: SlotMap::with_key(),
+            protocols: SlotMap::with_key(),
             registered_classes: HashMap::new(),
             registered_metaclasses: HashMap::new(),
             selectors_by_name: HashMap::new(),
+            registered_protocols: HashMap::new(),
+            pending_categories: HashMap::new(),
+            small_object_classes: [None; SMALL_OBJECT_MASK + 1],
+            block_classes: Vec::new(),
         }
     }
 
@@ -56,7 +81,15 @@ impl Context {
             )
         });
 
-        let metaclass_index = self.classes.insert(objc_class::default());
+        let metaclass_index = self.classes.insert_with_key(|index| {
+            objc_class::new(
+                Default::default(),
+                ClassData {
+                    index,
+                    ..Default::default()
+                },
+            )
+        });
 
         match superclass {
             // Metaclasses of root classes are precious little flowers and work a
@@ -103,4 +136,281 @@ impl Context {
                 })
             })
     }
+
+    /// Returns [None] if a protocol named [name] has already been registered.
+    pub fn allocate_protocol(&mut self, name: CString) -> Option<ProtocolKey> {
+        if self.registered_protocols.contains_key(&name) {
+            return None;
+        }
+
+        Some(
+            self.protocols
+                .insert_with_key(|index| Protocol::new(index, name)),
+        )
+    }
+
+    pub fn register_protocol(&mut self, protocol_key: ProtocolKey) {
+        let protocol = &mut self.protocols[protocol_key];
+        if protocol.registered || self.registered_protocols.contains_key(&protocol.name) {
+            return;
+        }
+
+        protocol.registered = true;
+        self.registered_protocols
+            .insert(protocol.name.clone(), protocol_key);
+    }
+
+    /// The `.cxx_construct` implementations of [class_key] and its
+    /// superclasses, from the root down, each with the class that defines it,
+    /// along with the selector to call them with.
+    pub fn cxx_constructors(
+        &mut self,
+        class_key: ClassKey,
+    ) -> (SelectorKey, Vec<(ClassKey, objc_imp)>) {
+        let selector = self.allocate_selector(CString::new(".cxx_construct").expect("valid utf8"));
+
+        let mut constructors = Vec::new();
+        let mut current = Some(class_key);
+        while let Some(class) = current.and_then(|class_key| self.classes.get(class_key)) {
+            constructors.extend(class.cxx_construct.map(|imp| (class.index, imp)));
+            current = class.superclass;
+        }
+        constructors.reverse();
+
+        (selector, constructors)
+    }
+
+    /// The `.cxx_destruct` implementations of [class_key] and its superclasses,
+    /// from the leaf up, along with the selector to call them with.
+    pub fn cxx_destructors(&mut self, class_key: ClassKey) -> (SelectorKey, Vec<objc_imp>) {
+        let selector = self.allocate_selector(CString::new(".cxx_destruct").expect("valid utf8"));
+
+        let mut destructors = Vec::new();
+        let mut current = Some(class_key);
+        while let Some(class) = current.and_then(|class_key| self.classes.get(class_key)) {
+            destructors.extend(class.cxx_destruct);
+            current = class.superclass;
+        }
+
+        (selector, destructors)
+    }
+
+    /// Whether [protocol_key] is, or inherits (directly or indirectly) from,
+    /// [other].
+    pub fn protocol_conforms_to(&self, protocol_key: ProtocolKey, other: ProtocolKey) -> bool {
+        let protocol = &self.protocols[protocol_key];
+        protocol_key == other
+            || protocol.name == self.protocols[other].name
+            || protocol
+                .protocols
+                .iter()
+                .any(|&inherited| self.protocol_conforms_to(inherited, other))
+    }
+
+    /// Whether [class_key] or any of its superclasses adopts a protocol that
+    /// conforms to [protocol_key].
+    pub fn class_conforms_to(&self, class_key: ClassKey, protocol_key: ProtocolKey) -> bool {
+        let mut current = Some(class_key);
+        while let Some(class) = current.and_then(|class_key| self.classes.get(class_key)) {
+            if class
+                .protocols
+                .iter()
+                .any(|&adopted| self.protocol_conforms_to(adopted, protocol_key))
+            {
+                return true;
+            }
+            current = class.superclass;
+        }
+        false
+    }
+
+    /// Makes [class_key] the class of small objects tagged with [tag]. Fails
+    /// if the tag is out of range or already taken by another class.
+    pub fn register_small_object_class(&mut self, class_key: ClassKey, tag: usize) -> bool {
+        match self.small_object_classes.get_mut(tag) {
+            Some(slot @ None) if tag != 0 => {
+                *slot = Some(class_key);
+                true
+            }
+            Some(Some(registered)) => *registered == class_key,
+            _ => false,
+        }
+    }
+
+    /// The class of [obj], which may be a small object or a block. [None] for
+    /// small objects whose tag has no class registered.
+    pub(crate) fn class_of(&self, obj: NonNull<Receiver>) -> Option<ClassKey> {
+        if let Some(tag) = small_object::tag(obj) {
+            return self.small_object_classes[tag];
+        }
+
+        self.block_class_of(obj)
+            .or_else(|| Some(**unsafe { obj.as_ref() }))
+    }
+
+    /// The class of [obj] if it's a block. A block's isa is the address of
+    /// one of the `_NSConcrete*Block` symbols rather than a class key.
+    pub(crate) fn block_class_of(&self, obj: NonNull<Receiver>) -> Option<ClassKey> {
+        if small_object::tag(obj).is_some() {
+            return None;
+        }
+        let isa = unsafe { obj.cast::<usize>().read() };
+        self.block_classes
+            .iter()
+            .find(|&&(address, _)| address == isa)
+            .map(|&(_, class_key)| class_key)
+    }
+
+    /// Removes the class [class_key], which must not have been registered,
+    /// along with its metaclass. Returns both so that the caller can free
+    /// what they hold without the context locked.
+    pub fn dispose_class_pair(&mut self, class_key: ClassKey) -> Vec<objc_class> {
+        let Some(class) = self.classes.remove(class_key) else {
+            return Vec::new();
+        };
+        let metaclass = self.classes.remove(class.is_a());
+        std::iter::once(class).chain(metaclass).collect()
+    }
+
+    pub fn register_class_pair(&mut self, class_key: ClassKey) {
+        let name = self.classes[class_key].name.clone();
+        self.registered_classes.insert(name.clone(), class_key);
+
+        // Categories loaded before their class are attached now, in the order
+        // they were loaded.
+        for category in self.pending_categories.remove(&name).into_iter().flatten() {
+            self.attach_category_to(class_key, category);
+        }
+    }
+
+    /// Attaches [category] to its class, or holds on to it until that class is
+    /// registered.
+    pub fn attach_category(&mut self, category: objc_category) {
+        match self.registered_classes.get(&category.class_name) {
+            Some(&class_key) => self.attach_category_to(class_key, category),
+            None => self
+                .pending_categories
+                .entry(category.class_name.clone())
+                .or_default()
+                .push(category),
+        }
+    }
+
+    fn attach_category_to(&mut self, class_key: ClassKey, category: objc_category) {
+        let objc_category {
+            instance_methods,
+            class_methods,
+            protocols,
+            properties,
+            ..
+        } = category;
+
+        // Method lists are searched front to back, so prepending means the
+        // most recently attached category wins over earlier ones and over the
+        // class itself.
+        let metaclass_key = self.classes[class_key].is_a();
+        for (class_key, methods) in [
+            (metaclass_key, class_methods),
+            (class_key, instance_methods),
+        ] {
+            let class = &mut self.classes[class_key];
+            for method in &methods {
+                class.note_cxx_method(&self.selectors[method.selector], method.imp);
+            }
+            class.methods.splice(0..0, methods);
+        }
+
+        let class = &mut self.classes[class_key];
+        class.protocols.extend(protocols);
+        class.properties.splice(0..0, properties);
+
+        self.flush_caches();
+    }
+
+    /// Finds the implementation [class_key]'s instances use for [selector],
+    /// searching superclasses and caching the result.
+    pub fn lookup_method(
+        &mut self,
+        class_key: ClassKey,
+        selector: SelectorKey,
+    ) -> Option<objc_imp> {
+        if let Some(&imp) = self.classes.get(class_key)?.dispatch_table.get(&selector) {
+            return Some(imp);
+        }
+
+        let mut current = Some(class_key);
+        let imp = loop {
+            let class = self.classes.get(current?)?;
+            if let Some(method) = class
+                .methods
+                .iter()
+                .find(|method| method.selector == selector)
+            {
+                break method.imp;
+            }
+            current = class.superclass;
+        };
+
+        self.classes[class_key].dispatch_table.insert(selector, imp);
+        Some(imp)
+    }
+
+    /// Whether instances of [class_key] must be sent `retain`, `release`,
+    /// `autorelease` and `retainCount` as messages, rather than having the
+    /// runtime manage their retain counts directly.
+    pub fn has_custom_rr(&self, class_key: ClassKey) -> bool {
+        const SELECTORS: [&str; 4] = ["retain", "release", "autorelease", "retainCount"];
+
+        let custom_rr = &self.classes[class_key].custom_rr;
+        match custom_rr.load(Ordering::Relaxed) {
+            CUSTOM_RR_YES => return true,
+            CUSTOM_RR_NO => return false,
+            _ => (),
+        }
+
+        // Selectors nobody has registered can't have implementations.
+        let selectors = SELECTORS
+            .iter()
+            .filter_map(|name| {
+                let name = CString::new(*name).expect("valid utf8");
+                self.selectors_by_name
+                    .get(&SelectorInfo::new(name))
+                    .copied()
+            })
+            .collect::<Vec<_>>();
+
+        let mut has_custom_rr = false;
+        let mut current = Some(class_key);
+        while let Some(class) = current.and_then(|class_key| self.classes.get(class_key)) {
+            if class
+                .methods
+                .iter()
+                .any(|method| selectors.contains(&method.selector))
+            {
+                has_custom_rr = true;
+                break;
+            }
+            current = class.superclass;
+        }
+
+        custom_rr.store(
+            if has_custom_rr {
+                CUSTOM_RR_YES
+            } else {
+                CUSTOM_RR_NO
+            },
+            Ordering::Relaxed,
+        );
+        has_custom_rr
+    }
+
+    /// Invalidates every class' dispatch table. Since subclasses cache
+    /// inherited implementations, a change to any method list may affect any
+    /// number of classes.
+    pub fn flush_caches(&mut self) {
+        for (_, class) in self.classes.iter_mut() {
+            class.dispatch_table.clear();
+            *class.custom_rr.get_mut() = CUSTOM_RR_UNKNOWN;
+        }
+    }
 }
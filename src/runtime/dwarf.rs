@@ -0,0 +1,287 @@
+//! Reads the language-specific data area (LSDA) compilers emit into
+//! `.gcc_except_table` for every function with landing pads, to find out
+//! what a frame wants to do with an exception passing through it.
+#![allow(non_upper_case_globals)]
+
+const DW_EH_PE_omit: u8 = 0xff;
+
+const DW_EH_PE_absptr: u8 = 0x00;
+const DW_EH_PE_uleb128: u8 = 0x01;
+const DW_EH_PE_udata2: u8 = 0x02;
+const DW_EH_PE_udata4: u8 = 0x03;
+const DW_EH_PE_udata8: u8 = 0x04;
+const DW_EH_PE_sleb128: u8 = 0x09;
+const DW_EH_PE_sdata2: u8 = 0x0a;
+const DW_EH_PE_sdata4: u8 = 0x0b;
+const DW_EH_PE_sdata8: u8 = 0x0c;
+
+const DW_EH_PE_pcrel: u8 = 0x10;
+const DW_EH_PE_textrel: u8 = 0x20;
+const DW_EH_PE_datarel: u8 = 0x30;
+const DW_EH_PE_funcrel: u8 = 0x40;
+
+const DW_EH_PE_indirect: u8 = 0x80;
+
+/// Addresses that encoded values may be relative to.
+pub struct Bases {
+    pub func_start: usize,
+    pub text: usize,
+    pub data: usize,
+}
+
+/// What a frame does with an exception thrown from a given instruction.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Nothing: unwinding carries on past the frame.
+    None,
+    /// Run the cleanup at the landing pad, then carry on unwinding.
+    Cleanup(usize),
+    /// Catch the exception with the handler selected by `switch_value`.
+    Catch {
+        landing_pad: usize,
+        switch_value: isize,
+    },
+    /// The instruction isn't expected to throw at all.
+    Terminate,
+}
+
+struct Reader(*const u8);
+
+impl Reader {
+    unsafe fn read<T: Copy>(&mut self) -> T {
+        let value = self.0.cast::<T>().read_unaligned();
+        self.0 = self.0.add(std::mem::size_of::<T>());
+        value
+    }
+
+    unsafe fn uleb128(&mut self) -> u64 {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read::<u8>();
+            result |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return result;
+            }
+        }
+    }
+
+    unsafe fn sleb128(&mut self) -> i64 {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read::<u8>();
+            result |= ((byte & 0x7f) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                // Sign-extend from the last byte read
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                return result;
+            }
+        }
+    }
+
+    /// Reads a value encoded as [encoding] describes. Returns [None] for
+    /// encodings this reader doesn't understand.
+    unsafe fn encoded(&mut self, encoding: u8, bases: &Bases) -> Option<usize> {
+        let position = self.0.addr();
+        let value = match encoding & 0x0f {
+            DW_EH_PE_absptr => self.read::<usize>(),
+            DW_EH_PE_uleb128 => self.uleb128() as usize,
+            DW_EH_PE_udata2 => self.read::<u16>() as usize,
+            DW_EH_PE_udata4 => self.read::<u32>() as usize,
+            DW_EH_PE_udata8 => self.read::<u64>() as usize,
+            DW_EH_PE_sleb128 => self.sleb128() as usize,
+            DW_EH_PE_sdata2 => self.read::<i16>() as usize,
+            DW_EH_PE_sdata4 => self.read::<i32>() as usize,
+            DW_EH_PE_sdata8 => self.read::<i64>() as usize,
+            _ => return None,
+        };
+
+        // A zero value is null regardless of what it's relative to
+        if value == 0 {
+            return Some(0);
+        }
+
+        let base = match encoding & 0x70 {
+            DW_EH_PE_absptr => 0,
+            DW_EH_PE_pcrel => position,
+            DW_EH_PE_textrel => bases.text,
+            DW_EH_PE_datarel => bases.data,
+            DW_EH_PE_funcrel => bases.func_start,
+            _ => return None,
+        };
+        let value = base.wrapping_add(value);
+
+        Some(if encoding & DW_EH_PE_indirect != 0 {
+            std::ptr::with_exposed_provenance::<usize>(value).read()
+        } else {
+            value
+        })
+    }
+}
+
+fn encoded_size(encoding: u8) -> Option<usize> {
+    match encoding & 0x0f {
+        DW_EH_PE_absptr => Some(std::mem::size_of::<usize>()),
+        DW_EH_PE_udata2 | DW_EH_PE_sdata2 => Some(2),
+        DW_EH_PE_udata4 | DW_EH_PE_sdata4 => Some(4),
+        DW_EH_PE_udata8 | DW_EH_PE_sdata8 => Some(8),
+        _ => None,
+    }
+}
+
+/// Finds what the frame described by [lsda] does with an exception thrown
+/// from [ip]. [catches] is given the type table entry of each catch clause in
+/// turn (0 for a catch-all) and decides whether it matches the exception.
+///
+/// # Safety
+///
+/// [lsda] must point to a well-formed LSDA for the function containing [ip].
+pub unsafe fn find_action(
+    lsda: *const u8,
+    ip: usize,
+    bases: &Bases,
+    mut catches: impl FnMut(usize) -> bool,
+) -> Option<Action> {
+    let mut reader = Reader(lsda);
+
+    let landing_pad_start = match reader.read::<u8>() {
+        DW_EH_PE_omit => bases.func_start,
+        encoding => reader.encoded(encoding, bases)?,
+    };
+
+    let type_encoding = reader.read::<u8>();
+    let type_table = match type_encoding {
+        DW_EH_PE_omit => None,
+        _ => {
+            let offset = reader.uleb128() as usize;
+            Some(reader.0.add(offset))
+        }
+    };
+
+    let call_site_encoding = reader.read::<u8>();
+    let call_site_table_length = reader.uleb128() as usize;
+    let action_table = reader.0.add(call_site_table_length);
+
+    while reader.0 < action_table {
+        let start = reader.encoded(call_site_encoding, bases)?;
+        let length = reader.encoded(call_site_encoding, bases)?;
+        let landing_pad = reader.encoded(call_site_encoding, bases)?;
+        let action = reader.uleb128() as usize;
+
+        // Call sites are sorted, so we've gone past where [ip] would be
+        let start = bases.func_start + start;
+        if ip < start {
+            break;
+        }
+        if ip >= start + length {
+            continue;
+        }
+
+        if landing_pad == 0 {
+            return Some(Action::None);
+        }
+        let landing_pad = landing_pad_start + landing_pad;
+        if action == 0 {
+            return Some(Action::Cleanup(landing_pad));
+        }
+
+        // Walk the chain of actions, looking for a catch clause that matches
+        let mut has_cleanup = false;
+        let mut record = Reader(action_table.add(action - 1));
+        loop {
+            let switch_value = record.sleb128() as isize;
+            let next_position = record.0;
+            let next = record.sleb128();
+
+            if switch_value == 0 {
+                has_cleanup = true;
+            } else if switch_value > 0 {
+                let size = encoded_size(type_encoding)?;
+                let entry = type_table?.sub(switch_value as usize * size);
+                let type_info = Reader(entry).encoded(type_encoding, bases)?;
+                if catches(type_info) {
+                    return Some(Action::Catch {
+                        landing_pad,
+                        switch_value,
+                    });
+                }
+            }
+
+            if next == 0 {
+                break;
+            }
+            record = Reader(next_position.offset(next as isize));
+        }
+
+        return Some(if has_cleanup {
+            Action::Cleanup(landing_pad)
+        } else {
+            Action::None
+        });
+    }
+
+    Some(Action::Terminate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_action() {
+        let mut call_sites = Vec::new();
+        for (start, length, landing_pad, action) in [
+            (0x10u32, 0x10u32, 0x40u32, 1u8),
+            (0x20, 0x10, 0x50, 0),
+            (0x30, 0x10, 0, 0),
+        ] {
+            call_sites.extend(start.to_ne_bytes());
+            call_sites.extend(length.to_ne_bytes());
+            call_sites.extend(landing_pad.to_ne_bytes());
+            call_sites.push(action);
+        }
+        // Catch type 2, then type 1, then run a cleanup
+        let actions = [2, 1, 1, 1, 0, 0];
+        let types = [0x2222usize, 0x1111].map(usize::to_ne_bytes).concat();
+
+        let mut lsda = vec![DW_EH_PE_omit, DW_EH_PE_absptr];
+        lsda.push((2 + call_sites.len() + actions.len() + types.len()) as u8);
+        lsda.extend([DW_EH_PE_udata4, call_sites.len() as u8]);
+        lsda.extend(call_sites);
+        lsda.extend(actions);
+        lsda.extend(types);
+
+        let bases = Bases {
+            func_start: 0x1000,
+            text: 0,
+            data: 0,
+        };
+        let find = |ip, catches: &dyn Fn(usize) -> bool| unsafe {
+            find_action(lsda.as_ptr(), ip, &bases, catches).unwrap()
+        };
+
+        assert_eq!(
+            find(0x1015, &|type_info| type_info == 0x1111),
+            Action::Catch {
+                landing_pad: 0x1040,
+                switch_value: 1
+            }
+        );
+        assert_eq!(
+            find(0x1015, &|type_info| type_info == 0x2222),
+            Action::Catch {
+                landing_pad: 0x1040,
+                switch_value: 2
+            }
+        );
+        assert_eq!(find(0x1015, &|_| false), Action::Cleanup(0x1040));
+        assert_eq!(find(0x1025, &|_| true), Action::Cleanup(0x1050));
+        assert_eq!(find(0x1035, &|_| true), Action::None);
+        assert_eq!(find(0x1045, &|_| true), Action::Terminate);
+    }
+}
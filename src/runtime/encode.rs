@@ -0,0 +1,58 @@
+//! Type encodings of the Rust types that can cross into Objective-C, so that
+//! methods implemented in Rust can describe their own signatures.
+
+use std::ffi::{c_char, c_void};
+
+use super::{class::Class, message::id, selector::SEL};
+
+/// A type that can be passed to or returned from a method, with the type
+/// encoding describing it.
+///
+/// # Safety
+///
+/// The type must be passed and returned the way C passes the type its
+/// encoding describes.
+pub unsafe trait Encode {
+    const ENCODING: &'static str;
+}
+
+macro_rules! encode {
+    ($($ty:ty => $encoding:literal),* $(,)?) => {
+        $(unsafe impl Encode for $ty {
+            const ENCODING: &'static str = $encoding;
+        })*
+    };
+}
+
+encode! {
+    () => "v",
+    bool => "B",
+    i8 => "c",
+    i16 => "s",
+    i32 => "i",
+    i64 => "q",
+    u8 => "C",
+    u16 => "S",
+    u32 => "I",
+    u64 => "Q",
+    f32 => "f",
+    f64 => "d",
+    id => "@",
+    Class => "#",
+    SEL => ":",
+    *const c_char => "*",
+    *mut c_void => "^v",
+}
+
+// Pointer-sized integers are encoded as the C type of the same size
+#[cfg(target_pointer_width = "64")]
+encode! {
+    isize => "q",
+    usize => "Q",
+}
+
+#[cfg(target_pointer_width = "32")]
+encode! {
+    isize => "i",
+    usize => "I",
+}
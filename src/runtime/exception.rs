@@ -0,0 +1,187 @@
+//! Objective-C exceptions, thrown with the Itanium unwinder. An exception in
+//! flight is an [ObjcException]: the unwinder's header followed by the thrown
+//! object and what the personality routine learnt about where it's going.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_void, CStr},
+    sync::{LazyLock, Mutex},
+};
+
+use super::{
+    message::id,
+    unwind::{
+        _Unwind_DeleteException, _Unwind_Exception, _Unwind_Reason_Code, _Unwind_Resume_or_Rethrow,
+    },
+};
+use crate::ffi::{
+    names::{CXA_BEGIN_CATCH, CXA_END_CATCH, CXA_RETHROW},
+    objc_release, object_getClassName,
+};
+
+/// Identifies exceptions thrown by this runtime: "GNUCOBJC", as GCC's.
+pub const OBJC_EXCEPTION_CLASS: u64 = u64::from_be_bytes(*b"GNUCOBJC");
+/// Exceptions thrown by the GNU C++ runtime, and ones it rethrew with
+/// `std::rethrow_exception`.
+const CXX_EXCEPTION_CLASS: u64 = u64::from_be_bytes(*b"GNUCC++\0");
+const CXX_DEPENDENT_EXCEPTION_CLASS: u64 = u64::from_be_bytes(*b"GNUCC++\x01");
+
+pub type objc_uncaught_exception_handler = Option<extern "C" fn(id)>;
+
+pub static UNCAUGHT_HANDLER: Mutex<objc_uncaught_exception_handler> = Mutex::new(None);
+
+/// The C++ runtime's functions for catching its own exceptions. C++
+/// exceptions caught by `@catch` go through these so that the C++ runtime's
+/// bookkeeping, like `std::uncaught_exceptions`, stays right.
+struct CxxRuntime {
+    begin_catch: unsafe extern "C" fn(*mut c_void) -> *mut c_void,
+    end_catch: unsafe extern "C" fn(),
+    rethrow: unsafe extern "C-unwind" fn() -> !,
+}
+
+/// Looked up rather than linked against, since not every program using the
+/// runtime has a C++ runtime loaded.
+static CXX_RUNTIME: LazyLock<Option<CxxRuntime>> = LazyLock::new(|| {
+    let symbol = |name: &CStr| {
+        let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+        (!symbol.is_null()).then_some(symbol)
+    };
+    unsafe {
+        Some(CxxRuntime {
+            begin_catch: std::mem::transmute::<
+                *mut c_void,
+                unsafe extern "C" fn(*mut c_void) -> *mut c_void,
+            >(symbol(CXA_BEGIN_CATCH)?),
+            end_catch: std::mem::transmute::<*mut c_void, unsafe extern "C" fn()>(symbol(
+                CXA_END_CATCH,
+            )?),
+            rethrow: std::mem::transmute::<*mut c_void, unsafe extern "C-unwind" fn() -> !>(
+                symbol(CXA_RETHROW)?,
+            ),
+        })
+    }
+});
+
+/// The C++ runtime, if [exception] is a C++ exception and one is loaded.
+fn cxx_runtime(exception: *mut _Unwind_Exception) -> Option<&'static CxxRuntime> {
+    let exception_class = unsafe { exception.as_ref()? }.exception_class;
+    match exception_class {
+        CXX_EXCEPTION_CLASS | CXX_DEPENDENT_EXCEPTION_CLASS => CXX_RUNTIME.as_ref(),
+        _ => None,
+    }
+}
+
+#[repr(C)]
+pub struct ObjcException {
+    pub header: _Unwind_Exception,
+    pub object: id,
+    /// The catch clause found in the search phase, for the cleanup phase to
+    /// jump to without searching again.
+    pub switch_value: isize,
+    pub landing_pad: usize,
+    /// Set while the exception is being rethrown, so that leaving the catch
+    /// clause that caught it doesn't destroy it.
+    pub rethrown: bool,
+}
+
+impl ObjcException {
+    /// Allocates an exception for [object], which must already be retained.
+    pub fn allocate(object: id) -> *mut _Unwind_Exception {
+        let exception = Box::new(Self {
+            header: _Unwind_Exception::new(OBJC_EXCEPTION_CLASS, Some(cleanup)),
+            object,
+            switch_value: 0,
+            landing_pad: 0,
+            rethrown: false,
+        });
+        Box::into_raw(exception).cast()
+    }
+
+    /// [exception] as one of ours, if it was thrown by this runtime.
+    pub fn from_header(exception: *mut _Unwind_Exception) -> Option<&'static mut Self> {
+        let exception = unsafe { exception.as_mut()? };
+        (exception.exception_class == OBJC_EXCEPTION_CLASS)
+            .then(|| unsafe { &mut *(exception as *mut _Unwind_Exception).cast::<Self>() })
+    }
+}
+
+/// Frees an exception once it's been caught for good, whichever language
+/// caught it.
+extern "C" fn cleanup(_reason: _Unwind_Reason_Code, exception: *mut _Unwind_Exception) {
+    let exception = unsafe { Box::from_raw(exception.cast::<ObjcException>()) };
+    objc_release(exception.object);
+}
+
+thread_local! {
+    /// Exceptions whose catch clauses are currently running on this thread,
+    /// innermost last.
+    static CAUGHT: RefCell<Vec<*mut _Unwind_Exception>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Enters a catch clause for [exception], returning the thrown object, or nil
+/// for exceptions thrown by other languages.
+pub fn begin_catch(exception: *mut _Unwind_Exception) -> id {
+    CAUGHT.with_borrow_mut(|caught| caught.push(exception));
+    if let Some(cxx_runtime) = cxx_runtime(exception) {
+        unsafe { (cxx_runtime.begin_catch)(exception.cast()) };
+        return None;
+    }
+    let exception = ObjcException::from_header(exception)?;
+    exception.rethrown = false;
+    exception.object
+}
+
+/// Leaves the innermost catch clause, destroying its exception unless it is
+/// being rethrown.
+pub fn end_catch() {
+    let exception = CAUGHT
+        .with_borrow_mut(Vec::pop)
+        .expect("objc_end_catch called outside a catch clause");
+
+    if let Some(cxx_runtime) = cxx_runtime(exception) {
+        return unsafe { (cxx_runtime.end_catch)() };
+    }
+    if ObjcException::from_header(exception).is_some_and(|exception| exception.rethrown) {
+        return;
+    }
+    unsafe { _Unwind_DeleteException(exception) };
+}
+
+/// Rethrows [exception] from the catch clause or cleanup handling it.
+pub fn rethrow(exception: *mut _Unwind_Exception) -> ! {
+    // The C++ runtime keeps track of the exceptions it's handling, and must
+    // know this one is still in flight.
+    if CAUGHT.with_borrow(|caught| caught.last() == Some(&exception)) {
+        if let Some(cxx_runtime) = cxx_runtime(exception) {
+            unsafe { (cxx_runtime.rethrow)() };
+        }
+    }
+
+    if let Some(exception) = ObjcException::from_header(exception) {
+        exception.rethrown = true;
+    }
+    unsafe { _Unwind_Resume_or_Rethrow(exception) };
+    uncaught(exception)
+}
+
+/// Called when [exception] reaches the top of the stack without being caught.
+/// The uncaught exception handler gets a look at Objective-C exceptions before
+/// the process aborts.
+pub fn uncaught(exception: *mut _Unwind_Exception) -> ! {
+    match ObjcException::from_header(exception) {
+        Some(exception) => {
+            let handler = *UNCAUGHT_HANDLER.lock().expect("poisoned mutex");
+            if let Some(handler) = handler {
+                handler(exception.object);
+            }
+
+            let class_name = unsafe { CStr::from_ptr(object_getClassName(exception.object)) };
+            eprintln!(
+                "Terminating due to uncaught exception of class {}",
+                class_name.to_string_lossy()
+            );
+        }
+        None => eprintln!("Terminating due to uncaught foreign exception"),
+    }
+    std::process::abort()
+}
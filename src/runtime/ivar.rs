@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use pow_of_2::PowOf2;
 
 #[allow(non_camel_case_types)]
@@ -7,9 +9,14 @@ pub struct objc_ivar {
     pub(crate) alignment: PowOf2<usize>,
     pub(crate) types: String,
     pub(crate) offset: usize,
+    pub(crate) ownership: Ownership,
 }
 
-enum _Ownership {
+/// How the runtime manages an object stored in an ivar.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ownership {
+    /// Nothing is known about the ivar, so the caller picks a default.
+    #[default]
     Invalid,
     Strong,
     Weak,
@@ -17,15 +24,105 @@ enum _Ownership {
 }
 
 impl objc_ivar {
+    /// An ivar whose ownership is derived from [types]: objects and blocks
+    /// are strong, as under ARC, until a layout says otherwise.
     pub fn new(name: String, size: usize, alignment: u8, types: String) -> Self {
-        Self {
+        let mut ivar = Self {
             name,
             size,
             alignment: PowOf2::from_exp(alignment),
             types,
             offset: 0,
+            ownership: Ownership::Invalid,
+        };
+        if ivar.is_object() {
+            ivar.ownership = Ownership::Strong;
         }
+        ivar
+    }
+
+    /// Whether the type encoding says this ivar holds an object or a block.
+    pub(crate) fn is_object(&self) -> bool {
+        self.types.starts_with('@')
     }
 }
 
+/// Encodes a set of word indices as an ivar layout bitmap: a sequence of
+/// bytes whose high nibble is a number of words to skip and whose low nibble
+/// is a number of words to mark, terminated by a zero byte.
+pub(crate) fn encode_layout(words: &BTreeSet<usize>) -> Box<[u8]> {
+    let mut layout = Vec::new();
+    let end = words.last().map_or(0, |&word| word + 1);
+    let mut word = 0;
+
+    while word < end {
+        let mut skip = 0;
+        while !words.contains(&word) {
+            skip += 1;
+            word += 1;
+        }
+        let mut scan = 0;
+        while words.contains(&word) {
+            scan += 1;
+            word += 1;
+        }
+
+        while skip > 0xf {
+            layout.push(0xf0);
+            skip -= 0xf;
+        }
+        while scan > 0xf {
+            layout.push((skip << 4) as u8 | 0xf);
+            skip = 0;
+            scan -= 0xf;
+        }
+        layout.push((skip << 4) as u8 | scan as u8);
+    }
+
+    layout.push(0);
+    layout.into_boxed_slice()
+}
+
+/// Decodes a bitmap produced by [encode_layout] back into word indices.
+///
+/// # Safety
+///
+/// [layout] must point to a zero-terminated layout bitmap.
+pub(crate) unsafe fn decode_layout(mut layout: *const u8) -> BTreeSet<usize> {
+    let mut words = BTreeSet::new();
+    let mut word = 0;
+
+    while *layout != 0 {
+        word += (*layout >> 4) as usize;
+        for _ in 0..(*layout & 0xf) {
+            words.insert(word);
+            word += 1;
+        }
+        layout = layout.add(1);
+    }
+
+    words
+}
+
 pub type Ivar = Option<std::ptr::NonNull<objc_ivar>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_bitmap() {
+        let words = BTreeSet::from([1, 2, 5]);
+        let layout = encode_layout(&words);
+        assert_eq!(&*layout, [0x12, 0x21, 0x00]);
+        assert_eq!(unsafe { decode_layout(layout.as_ptr()) }, words);
+
+        // Runs longer than a nibble are split across bytes
+        let words = (20..40).collect::<BTreeSet<_>>();
+        let layout = encode_layout(&words);
+        assert_eq!(&*layout, [0xf0, 0x5f, 0x05, 0x00]);
+        assert_eq!(unsafe { decode_layout(layout.as_ptr()) }, words);
+
+        assert_eq!(&*encode_layout(&BTreeSet::new()), [0x00]);
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    hint,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// A minimal lock for very short critical sections, such as copying a single
+/// property value.
+pub struct SpinLock {
+    locked: AtomicBool,
+}
+
+pub struct SpinLockGuard<'a> {
+    lock: &'a SpinLock,
+}
+
+impl SpinLock {
+    pub const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<'_> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+        SpinLockGuard { lock: self }
+    }
+
+    /// Locks both [a] and [b] in a consistent order so that two threads locking
+    /// the same pair can't deadlock. [b] may be the same lock as [a].
+    pub fn lock_two<'a>(
+        a: &'a SpinLock,
+        b: &'a SpinLock,
+    ) -> (SpinLockGuard<'a>, Option<SpinLockGuard<'a>>) {
+        if std::ptr::eq(a, b) {
+            return (a.lock(), None);
+        }
+
+        let (first, second) = if (a as *const SpinLock) < (b as *const SpinLock) {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        let first = first.lock();
+        (first, Some(second.lock()))
+    }
+}
+
+impl Drop for SpinLockGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+pub const STRIPE_COUNT: usize = 64;
+
+/// A fixed set of [T]s, one of which is picked for a given address. This lets
+/// unrelated objects mostly avoid contending on the same lock without giving
+/// every object a lock of its own.
+pub struct StripedMap<T> {
+    stripes: [T; STRIPE_COUNT],
+}
+
+impl<T> StripedMap<T> {
+    pub const fn new(stripes: [T; STRIPE_COUNT]) -> Self {
+        Self { stripes }
+    }
+
+    pub fn for_address<U>(&self, address: *const U) -> &T {
+        let address = address.addr();
+        &self.stripes[((address >> 4) ^ (address >> 9)) % STRIPE_COUNT]
+    }
+}
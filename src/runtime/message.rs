@@ -1,4 +1,7 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use super::context::ClassKey;
 
@@ -44,6 +47,19 @@ impl<T> Repr<T> {
     pub const fn is_a(&self) -> ClassKey {
         self.is_a.0
     }
+
+    /// Atomically replaces this object's class, returning the old one.
+    pub fn swap__is_a(&self, class_key: ClassKey) -> ClassKey {
+        const _: () = assert!(std::mem::size_of::<Receiver>() == std::mem::size_of::<AtomicU64>());
+
+        // The bits are reinterpreted rather than converted, since other code
+        // reads [Repr::is_a] as a plain [ClassKey].
+        let is_a = std::ptr::addr_of!(self.is_a) as *const AtomicU64;
+        assert!(is_a.is_aligned(), "misaligned isa");
+        let new = unsafe { std::mem::transmute::<ClassKey, u64>(class_key) };
+        let old = unsafe { &*is_a }.swap(new, Ordering::AcqRel);
+        unsafe { std::mem::transmute::<u64, ClassKey>(old) }
+    }
 }
 
 impl<T> Deref for Repr<T> {
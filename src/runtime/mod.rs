@@ -1,14 +1,31 @@
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
+pub mod autorelease;
+pub mod block;
+pub mod category;
 pub mod class;
+pub mod class_builder;
+pub mod closure;
 pub mod context;
+pub mod dwarf;
+pub mod encode;
+pub mod exception;
 pub mod ivar;
+pub mod lock;
 pub mod message;
 pub mod method;
 pub mod object;
 pub mod property;
 pub mod protocol;
+pub mod refcount;
 pub mod selector;
+pub mod side_table;
+pub mod small_object;
+pub mod sync;
+pub mod trampoline;
+/// cbindgen:ignore
+pub mod unwind;
+pub mod weak;
 
 pub use class::Class;
 pub use ivar::Ivar;
@@ -2,7 +2,7 @@ use std::alloc::{Allocator, Global, Layout};
 use std::ptr::NonNull;
 
 use super::context::ClassKey;
-use super::message::Repr;
+use super::message::{id, Repr};
 
 #[repr(C)]
 pub struct ObjectData {
@@ -28,22 +28,76 @@ impl std::ops::DerefMut for objc_object {
     }
 }
 
+/// An object, as methods implemented in Rust see their receiver. It's opaque,
+/// since the receiver may be a small object that isn't in memory at all.
+#[repr(C)]
+pub struct Object {
+    _private: [u8; 0],
+}
+
+impl Object {
+    pub fn as_id(&self) -> id {
+        Some(NonNull::from(self).cast())
+    }
+}
+
+/// Bookkeeping stored immediately before every object allocated by the
+/// runtime, so the object can be freed without consulting its class (which
+/// may have changed since it was allocated).
+#[repr(C)]
+struct ObjectHeader {
+    /// Layout of the whole allocation, header included.
+    layout: Layout,
+    /// Offset of the object from the start of the allocation.
+    offset: usize,
+}
+
 impl objc_object {
     pub fn new(class_key: ClassKey, dt_layout: Layout) -> NonNull<Self> {
-        let (layout, dt_offset) = Layout::new::<Repr<ObjectData>>()
+        let (object_layout, dt_offset) = Layout::new::<Repr<ObjectData>>()
             .extend(dt_layout)
             .expect("bad layout I guess");
 
         assert_eq!(dt_offset, memoffset::offset_of!(Repr<ObjectData>, data));
 
-        let mut obj: NonNull<Self> = Global
+        // The header goes directly before the object, so it can be found from
+        // the object pointer alone.
+        let header_layout = Layout::new::<ObjectHeader>();
+        let align = object_layout.align().max(header_layout.align());
+        let offset = header_layout.size().next_multiple_of(align);
+        let layout = Layout::from_size_align(offset + object_layout.size(), align)
+            .expect("bad layout I guess");
+
+        let allocation: NonNull<u8> = Global
             .allocate_zeroed(layout)
             .expect("failed to allocate")
             .cast();
 
-        // unsafe { std::mem::transmute(Global.allocate_zeroed(layout).expect("failed to allocate")) }
+        let mut obj: NonNull<Self> = unsafe {
+            let obj = allocation.add(offset);
+            obj.cast::<ObjectHeader>()
+                .sub(1)
+                .write(ObjectHeader { layout, offset });
+            obj.cast()
+        };
+
         unsafe { obj.as_mut() }.set__is_a(class_key);
 
         obj
     }
+
+    /// Frees an object allocated by [objc_object::new]. The object must already
+    /// have been destructed.
+    pub(crate) unsafe fn dispose(obj: NonNull<Self>) {
+        let ObjectHeader { layout, offset } = obj.cast::<ObjectHeader>().sub(1).read();
+        Global.deallocate(obj.cast::<u8>().sub(offset), layout);
+    }
+
+    /// Pointer to the ivar storage [offset] bytes into [obj]'s ivars. Takes a
+    /// raw pointer so the result may be used to access the whole allocation.
+    pub(crate) unsafe fn ivar_ptr(obj: NonNull<Self>, offset: usize) -> *mut u8 {
+        obj.as_ptr()
+            .cast::<u8>()
+            .add(memoffset::offset_of!(Repr<ObjectData>, data) + offset)
+    }
 }
@@ -1,9 +1,225 @@
-use super::selector::objc_selector;
+use std::ffi::{c_char, CStr, CString};
+
+use super::context::{Context, SelectorKey};
+
+/// A single attribute of a property, such as `T` (type) or `V` (backing ivar).
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct objc_property_attribute_t {
+    pub name: *const c_char,
+    pub value: *const c_char,
+}
+
+pub(crate) struct PropertyAttribute {
+    pub(crate) name: CString,
+    pub(crate) value: CString,
+}
 
 pub struct Property {
-    pub(crate) name: String,
-    attributes: String,
+    pub(crate) name: CString,
+    /// The encoded form of [Property::attribute_list], e.g.
+    /// `T@"NSString",C,N,V_name`.
+    pub(crate) attributes: CString,
+    pub(crate) attribute_list: Vec<PropertyAttribute>,
     type_: String,
-    getter: objc_selector,
-    setter: objc_selector,
+    /// From the `G` attribute, defaulting to the property's name.
+    pub(crate) getter: SelectorKey,
+    /// From the `S` attribute, defaulting to `set<Name>:`. Read-only
+    /// properties have no setter.
+    pub(crate) setter: Option<SelectorKey>,
+}
+
+impl Property {
+    /// Builds a property from a C array of attributes, encoding them into an
+    /// attribute string.
+    pub fn new(
+        context: &mut Context,
+        name: CString,
+        attributes: &[objc_property_attribute_t],
+    ) -> Self {
+        let attribute_list = attributes
+            .iter()
+            .map(|attribute| PropertyAttribute {
+                name: unsafe { CStr::from_ptr(attribute.name) }.to_owned(),
+                value: if attribute.value.is_null() {
+                    CString::default()
+                } else {
+                    unsafe { CStr::from_ptr(attribute.value) }.to_owned()
+                },
+            })
+            .collect();
+
+        Self::from_attribute_list(context, name, attribute_list)
+    }
+
+    /// Builds a property from an encoded attribute string.
+    pub fn from_attribute_string(context: &mut Context, name: CString, attributes: &str) -> Self {
+        Self::from_attribute_list(context, name, parse_attributes(attributes))
+    }
+
+    fn from_attribute_list(
+        context: &mut Context,
+        name: CString,
+        attribute_list: Vec<PropertyAttribute>,
+    ) -> Self {
+        let attributes = attribute_list
+            .iter()
+            .map(|attribute| {
+                let mut encoded = attribute.name.as_bytes().to_vec();
+                encoded.extend_from_slice(attribute.value.as_bytes());
+                encoded
+            })
+            .collect::<Vec<_>>()
+            .join(&b',');
+        let type_ = attribute_list
+            .iter()
+            .find(|attribute| attribute.name.as_bytes() == b"T")
+            .map(|attribute| attribute.value.to_str().expect("invalid utf8").to_owned())
+            .unwrap_or_default();
+
+        let find = |attribute_name: &[u8]| {
+            attribute_list
+                .iter()
+                .find(|attribute| attribute.name.as_bytes() == attribute_name)
+                .map(|attribute| attribute.value.clone())
+        };
+
+        let getter = find(b"G").unwrap_or_else(|| name.clone());
+        let setter = match find(b"R") {
+            Some(_) => None,
+            None => Some(find(b"S").unwrap_or_else(|| default_setter_name(&name))),
+        };
+
+        Self {
+            attributes: CString::new(attributes).expect("attributes contain a nul byte"),
+            attribute_list,
+            type_,
+            getter: context.allocate_selector(getter),
+            setter: setter.map(|setter| context.allocate_selector(setter)),
+            name,
+        }
+    }
+
+    pub(crate) fn attribute_value(&self, name: &CStr) -> Option<&CStr> {
+        self.attribute_list
+            .iter()
+            .find(|attribute| attribute.name.as_c_str() == name)
+            .map(|attribute| attribute.value.as_c_str())
+    }
+}
+
+/// `set<Name>:`, where `<Name>` is [name] with its first letter capitalized.
+fn default_setter_name(name: &CStr) -> CString {
+    let name = name.to_str().expect("invalid utf8");
+    let mut chars = name.chars();
+    let capitalized = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+        None => String::new(),
+    };
+    CString::new(format!("set{capitalized}:")).expect("name contains a nul byte")
+}
+
+/// Splits an attribute string into its attributes. Each attribute is a
+/// single-character name followed by its value; attributes are separated by
+/// commas, except where the comma is part of a quoted or bracketed type
+/// encoding such as `T{Pair=i,i}`.
+fn parse_attributes(attributes: &str) -> Vec<PropertyAttribute> {
+    let mut parsed = Vec::new();
+    let mut chars = attributes.chars().peekable();
+
+    while let Some(name) = chars.next() {
+        let mut value = String::new();
+        let mut depth = 0usize;
+        let mut quoted = false;
+
+        while let Some(&c) = chars.peek() {
+            match c {
+                ',' if depth == 0 && !quoted => break,
+                '"' => quoted = !quoted,
+                '{' | '(' | '[' if !quoted => depth += 1,
+                '}' | ')' | ']' if !quoted => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            value.push(c);
+            chars.next();
+        }
+        // Skip the separating comma, if any
+        chars.next();
+
+        parsed.push(PropertyAttribute {
+            name: CString::new(name.to_string()).expect("attribute name contains a nul byte"),
+            value: CString::new(value).expect("attribute value contains a nul byte"),
+        });
+    }
+
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_attributes() {
+        let parsed = parse_attributes(r#"T@"NSString",C,N,V_name"#);
+        let parsed = parsed
+            .iter()
+            .map(|attribute| {
+                (
+                    attribute.name.to_str().unwrap(),
+                    attribute.value.to_str().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parsed,
+            [
+                ("T", r#"@"NSString""#),
+                ("C", ""),
+                ("N", ""),
+                ("V", "_name")
+            ]
+        );
+
+        let parsed = parse_attributes("T{Pair=i,i},R,GfirstAndSecond");
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0].value.as_bytes(), b"{Pair=i,i}");
+        assert_eq!(parsed[2].value.as_bytes(), b"firstAndSecond");
+
+        assert!(parse_attributes("").is_empty());
+
+        let mut context = Context::new();
+        let property = Property::from_attribute_string(
+            &mut context,
+            CString::new("name").unwrap(),
+            r#"T@"NSString",C,N,V_name"#,
+        );
+        assert_eq!(
+            property.attributes.as_bytes(),
+            br#"T@"NSString",C,N,V_name"#
+        );
+        assert_eq!(property.type_, r#"@"NSString""#);
+        assert_eq!(
+            property.attribute_value(&CString::new("V").unwrap()),
+            Some(CString::new("_name").unwrap().as_c_str())
+        );
+
+        let read_only = Property::from_attribute_string(
+            &mut context,
+            CString::new("enabled").unwrap(),
+            "TB,R,GisEnabled",
+        );
+
+        let selector_name = |selector| {
+            context.selectors[selector]
+                .selector_info
+                .name
+                .to_str()
+                .unwrap()
+        };
+        assert_eq!(selector_name(property.getter), "name");
+        assert_eq!(selector_name(property.setter.unwrap()), "setName:");
+        assert_eq!(selector_name(read_only.getter), "isEnabled");
+        assert!(read_only.setter.is_none());
+    }
 }
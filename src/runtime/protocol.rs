@@ -1 +1,76 @@
-pub struct Protocol {}
+use std::ffi::{c_char, CString};
+
+use super::{
+    context::{ProtocolKey, SelectorKey},
+    property::Property,
+    selector::SEL,
+};
+
+/// The C-facing description of a method a protocol declares.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct objc_method_description {
+    pub name: SEL,
+    pub types: *const c_char,
+}
+
+pub(crate) struct MethodDescription {
+    pub(crate) selector: SelectorKey,
+    pub(crate) types: CString,
+}
+
+#[derive(Default)]
+pub struct Protocol {
+    pub(crate) index: ProtocolKey,
+    pub(crate) name: CString,
+    /// Protocols this protocol inherits from.
+    pub(crate) protocols: Vec<ProtocolKey>,
+    required_instance_methods: Vec<MethodDescription>,
+    optional_instance_methods: Vec<MethodDescription>,
+    required_class_methods: Vec<MethodDescription>,
+    optional_class_methods: Vec<MethodDescription>,
+    pub(crate) properties: Vec<Property>,
+    pub(crate) class_properties: Vec<Property>,
+    /// Protocols may only be modified between [objc_allocateProtocol] and
+    /// [objc_registerProtocol].
+    ///
+    /// [objc_allocateProtocol]: crate::ffi::objc_allocateProtocol
+    /// [objc_registerProtocol]: crate::ffi::objc_registerProtocol
+    pub(crate) registered: bool,
+}
+
+impl Protocol {
+    pub fn new(index: ProtocolKey, name: CString) -> Self {
+        Self {
+            index,
+            name,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn method_descriptions(
+        &self,
+        is_required: bool,
+        is_instance_method: bool,
+    ) -> &Vec<MethodDescription> {
+        match (is_required, is_instance_method) {
+            (true, true) => &self.required_instance_methods,
+            (false, true) => &self.optional_instance_methods,
+            (true, false) => &self.required_class_methods,
+            (false, false) => &self.optional_class_methods,
+        }
+    }
+
+    pub(crate) fn method_descriptions_mut(
+        &mut self,
+        is_required: bool,
+        is_instance_method: bool,
+    ) -> &mut Vec<MethodDescription> {
+        match (is_required, is_instance_method) {
+            (true, true) => &mut self.required_instance_methods,
+            (false, true) => &mut self.optional_instance_methods,
+            (true, false) => &mut self.required_class_methods,
+            (false, false) => &mut self.optional_class_methods,
+        }
+    }
+}
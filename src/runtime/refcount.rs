@@ -0,0 +1,23 @@
+use std::ptr::NonNull;
+
+use super::{message::Receiver, side_table};
+
+pub fn retain(obj: NonNull<Receiver>) {
+    side_table::lock(obj).retain(obj);
+}
+
+/// Returns true if this released the last reference, in which case the
+/// caller is responsible for deallocating [obj].
+pub fn release(obj: NonNull<Receiver>) -> bool {
+    side_table::lock(obj).release(obj)
+}
+
+pub fn retain_count(obj: NonNull<Receiver>) -> usize {
+    side_table::lock(obj).retain_count(obj)
+}
+
+/// Forgets [obj]'s retain count once it has been destroyed, and zeroes any
+/// weak references to it.
+pub fn clear(obj: NonNull<Receiver>) {
+    side_table::lock(obj).clear(obj);
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::BTreeMap,
+    ptr::NonNull,
+    sync::{Mutex, MutexGuard},
+};
+
+use super::{
+    lock::{StripedMap, STRIPE_COUNT},
+    message::Receiver,
+};
+
+#[derive(Default)]
+pub(crate) struct RefCount {
+    /// Retains beyond the first one.
+    extra: usize,
+    /// Set once the count drops to zero, so that retains and releases made
+    /// while the object is being deallocated don't resurrect it or free it
+    /// again.
+    deallocating: bool,
+}
+
+/// Per-object bookkeeping that doesn't live in the object itself. Retain
+/// counts and weak references share a table so that a weak load can check
+/// whether an object is being deallocated and retain it atomically.
+#[derive(Default)]
+pub(crate) struct SideTable {
+    /// Objects that aren't in here have a retain count of one, so freshly
+    /// created objects cost nothing.
+    refcounts: BTreeMap<usize, RefCount>,
+    /// Addresses of the weak variables currently pointing at each object.
+    weak_refs: BTreeMap<usize, Vec<usize>>,
+}
+
+static SIDE_TABLES: StripedMap<Mutex<SideTable>> = StripedMap::new(
+    [const {
+        Mutex::new(SideTable {
+            refcounts: BTreeMap::new(),
+            weak_refs: BTreeMap::new(),
+        })
+    }; STRIPE_COUNT],
+);
+
+/// Locks the side table responsible for [obj].
+pub(crate) fn lock(obj: NonNull<Receiver>) -> MutexGuard<'static, SideTable> {
+    SIDE_TABLES
+        .for_address(obj.as_ptr())
+        .lock()
+        .expect("poisoned mutex")
+}
+
+/// Locks the side tables for [a] and [b] in a consistent order so that two
+/// threads locking the same pair can't deadlock. Guards are returned in the
+/// same order as the objects. Either object may be nil; if both share a
+/// table, only the first guard is returned.
+pub(crate) fn lock_two(
+    a: Option<NonNull<Receiver>>,
+    b: Option<NonNull<Receiver>>,
+) -> (
+    Option<MutexGuard<'static, SideTable>>,
+    Option<MutexGuard<'static, SideTable>>,
+) {
+    let table =
+        |obj: Option<NonNull<Receiver>>| obj.map(|obj| SIDE_TABLES.for_address(obj.as_ptr()));
+    let lock = |table: &'static Mutex<SideTable>| table.lock().expect("poisoned mutex");
+
+    match (table(a), table(b)) {
+        (Some(a), Some(b)) if std::ptr::eq(a, b) => (Some(lock(a)), None),
+        (Some(a), Some(b)) if (a as *const _) < (b as *const _) => {
+            let a = lock(a);
+            (Some(a), Some(lock(b)))
+        }
+        (Some(a), Some(b)) => {
+            let b = lock(b);
+            (Some(lock(a)), Some(b))
+        }
+        (a, b) => (a.map(lock), b.map(lock)),
+    }
+}
+
+impl SideTable {
+    fn with_refcount<T>(
+        &mut self,
+        obj: NonNull<Receiver>,
+        f: impl FnOnce(&mut RefCount) -> T,
+    ) -> T {
+        let refcount = self.refcounts.entry(obj.as_ptr().addr()).or_default();
+        let result = f(refcount);
+        if refcount.extra == 0 && !refcount.deallocating {
+            self.refcounts.remove(&obj.as_ptr().addr());
+        }
+        result
+    }
+
+    /// Returns false, without retaining, if [obj] is being deallocated.
+    pub(crate) fn retain(&mut self, obj: NonNull<Receiver>) -> bool {
+        self.with_refcount(obj, |refcount| {
+            if !refcount.deallocating {
+                refcount.extra += 1;
+            }
+            !refcount.deallocating
+        })
+    }
+
+    /// Returns true if this released the last reference.
+    pub(crate) fn release(&mut self, obj: NonNull<Receiver>) -> bool {
+        self.with_refcount(obj, |refcount| {
+            if refcount.deallocating {
+                false
+            } else if refcount.extra == 0 {
+                refcount.deallocating = true;
+                true
+            } else {
+                refcount.extra -= 1;
+                false
+            }
+        })
+    }
+
+    pub(crate) fn retain_count(&mut self, obj: NonNull<Receiver>) -> usize {
+        self.with_refcount(obj, |refcount| refcount.extra + 1)
+    }
+
+    pub(crate) fn is_deallocating(&self, obj: NonNull<Receiver>) -> bool {
+        self.refcounts
+            .get(&obj.as_ptr().addr())
+            .is_some_and(|refcount| refcount.deallocating)
+    }
+
+    /// Records that the weak variable at [location] points to [obj].
+    pub(crate) fn register_weak(
+        &mut self,
+        obj: NonNull<Receiver>,
+        location: *mut Option<NonNull<Receiver>>,
+    ) {
+        self.weak_refs
+            .entry(obj.as_ptr().addr())
+            .or_default()
+            .push(location.expose_provenance());
+    }
+
+    pub(crate) fn unregister_weak(
+        &mut self,
+        obj: NonNull<Receiver>,
+        location: *mut Option<NonNull<Receiver>>,
+    ) {
+        let Some(locations) = self.weak_refs.get_mut(&obj.as_ptr().addr()) else {
+            return;
+        };
+        locations.retain(|&registered| registered != location.addr());
+        if locations.is_empty() {
+            self.weak_refs.remove(&obj.as_ptr().addr());
+        }
+    }
+
+    /// Forgets everything about [obj] once it has been destroyed, so that a
+    /// new object at the same address starts afresh. Weak variables still
+    /// pointing at it are set to nil.
+    pub(crate) fn clear(&mut self, obj: NonNull<Receiver>) {
+        self.refcounts.remove(&obj.as_ptr().addr());
+
+        for location in self
+            .weak_refs
+            .remove(&obj.as_ptr().addr())
+            .into_iter()
+            .flatten()
+        {
+            let location =
+                std::ptr::with_exposed_provenance_mut::<Option<NonNull<Receiver>>>(location);
+            if unsafe { location.read() } == Some(obj) {
+                unsafe { location.write(None) };
+            }
+        }
+    }
+}
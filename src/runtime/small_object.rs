@@ -0,0 +1,29 @@
+//! Small objects: values such as short integers and strings stored directly in
+//! an `id` instead of in memory. Objects are always at least word-aligned, so
+//! the low bits of a real object pointer are clear; a small object instead
+//! keeps a nonzero tag there, which selects its class, and its value in the
+//! remaining bits.
+
+use std::ptr::NonNull;
+
+use super::message::{id, Receiver};
+
+/// Number of low pointer bits holding a small object's tag.
+pub const SMALL_OBJECT_BITS: u32 = 3;
+pub const SMALL_OBJECT_MASK: usize = (1 << SMALL_OBJECT_BITS) - 1;
+
+/// The tag in [obj]'s low bits, or [None] if it is a real object pointer.
+pub fn tag(obj: NonNull<Receiver>) -> Option<usize> {
+    let tag = obj.as_ptr().addr() & SMALL_OBJECT_MASK;
+    (tag != 0).then_some(tag)
+}
+
+pub fn is_small_object(obj: id) -> bool {
+    obj.and_then(tag).is_some()
+}
+
+/// [obj], if it is an object in memory that can be dereferenced; nil for nil
+/// and small objects.
+pub fn in_memory(obj: id) -> id {
+    obj.filter(|&obj| tag(obj).is_none())
+}
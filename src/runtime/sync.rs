@@ -0,0 +1,97 @@
+//! Recursive per-object locks for `@synchronized`. Locks only exist while
+//! some thread holds or is waiting for them, and live in a striped table
+//! keyed by object address, so objects themselves don't pay for them.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Condvar, Mutex},
+    thread::{self, ThreadId},
+};
+
+use super::lock::{StripedMap, STRIPE_COUNT};
+
+struct SyncData {
+    owner: ThreadId,
+    /// Number of times the owner has entered the lock without exiting it.
+    depth: usize,
+    /// Threads blocked waiting for the lock, which keep it from being
+    /// removed from the table when the owner exits.
+    waiters: usize,
+}
+
+struct SyncStripe {
+    locks: Mutex<BTreeMap<usize, SyncData>>,
+    /// Signalled whenever a lock in this stripe becomes free.
+    released: Condvar,
+}
+
+static SYNC_TABLE: StripedMap<SyncStripe> = StripedMap::new(
+    [const {
+        SyncStripe {
+            locks: Mutex::new(BTreeMap::new()),
+            released: Condvar::new(),
+        }
+    }; STRIPE_COUNT],
+);
+
+/// Takes the lock for [address], blocking while another thread holds it.
+pub fn enter(address: usize) {
+    let stripe = SYNC_TABLE.for_address(std::ptr::without_provenance::<u8>(address));
+    let current = thread::current().id();
+    let mut locks = stripe.locks.lock().expect("poisoned mutex");
+
+    loop {
+        match locks.get_mut(&address) {
+            None => {
+                locks.insert(
+                    address,
+                    SyncData {
+                        owner: current,
+                        depth: 1,
+                        waiters: 0,
+                    },
+                );
+                return;
+            }
+            Some(data) if data.depth == 0 => {
+                data.owner = current;
+                data.depth = 1;
+                return;
+            }
+            Some(data) if data.owner == current => {
+                data.depth += 1;
+                return;
+            }
+            Some(data) => {
+                data.waiters += 1;
+                locks = stripe.released.wait(locks).expect("poisoned mutex");
+                locks.get_mut(&address).expect("lock has waiters").waiters -= 1;
+            }
+        }
+    }
+}
+
+/// Releases one level of the lock for [address]. Returns false, changing
+/// nothing, if the current thread doesn't hold it.
+pub fn exit(address: usize) -> bool {
+    let stripe = SYNC_TABLE.for_address(std::ptr::without_provenance::<u8>(address));
+    let current = thread::current().id();
+    let mut locks = stripe.locks.lock().expect("poisoned mutex");
+
+    let Some(data) = locks.get_mut(&address) else {
+        return false;
+    };
+    if data.owner != current || data.depth == 0 {
+        return false;
+    }
+
+    data.depth -= 1;
+    if data.depth == 0 {
+        if data.waiters == 0 {
+            locks.remove(&address);
+        } else {
+            stripe.released.notify_all();
+        }
+    }
+    true
+}
@@ -0,0 +1,187 @@
+//! Trampolines that let blocks be called as method implementations. A method
+//! is called with `(self, _cmd, args...)` but a block's invoke function
+//! expects `(block, self, args...)`, so each trampoline moves `self` over
+//! `_cmd`, loads its block into the first argument and jumps to the block's
+//! invoke function.
+//!
+//! Trampolines are allocated in pairs of pages: a writable data page holding
+//! each trampoline's block, followed by an executable page of identical
+//! trampolines, each of which finds its block at the same offset one page
+//! back.
+
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, Mutex},
+};
+
+use super::{block::Block_layout, method::objc_imp};
+
+/// Bytes per trampoline, and per block pointer in the data page.
+const SLOT_SIZE: usize = 16;
+
+static PAGE_SIZE: LazyLock<usize> =
+    LazyLock::new(|| unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize);
+
+/// A trampoline for [page_size]-byte pages. On x86-64, blocks that return
+/// structures through memory are passed the return address first, so they
+/// take their block and `self` one argument later.
+#[cfg(target_arch = "x86_64")]
+fn template(page_size: usize, stret: bool) -> Option<[u8; SLOT_SIZE]> {
+    // The block is loaded relative to the end of the 10 bytes before it
+    let [d0, d1, d2, d3] = (-(page_size as i32) - 10).to_le_bytes();
+    Some(if stret {
+        [
+            0x48, 0x89, 0xf2, // mov rdx, rsi
+            0x48, 0x8b, 0x35, d0, d1, d2, d3, // mov rsi, [rip - page_size - 10]
+            0xff, 0x66, 0x10, // jmp [rsi + 16]
+            0xcc, 0xcc, 0xcc, // int3
+        ]
+    } else {
+        [
+            0x48, 0x89, 0xfe, // mov rsi, rdi
+            0x48, 0x8b, 0x3d, d0, d1, d2, d3, // mov rdi, [rip - page_size - 10]
+            0xff, 0x67, 0x10, // jmp [rdi + 16]
+            0xcc, 0xcc, 0xcc, // int3
+        ]
+    })
+}
+
+/// A trampoline for [page_size]-byte pages. Structures are returned through
+/// a dedicated register, so [stret] makes no difference.
+#[cfg(target_arch = "aarch64")]
+fn template(page_size: usize, _stret: bool) -> Option<[u8; SLOT_SIZE]> {
+    // The block is loaded relative to the instruction loading it
+    let offset = ((-(page_size as i32) - 4) / 4) as u32 & 0x7ffff;
+    let instructions: [u32; 4] = [
+        0xaa0003e1,               // mov x1, x0
+        0x58000000 | offset << 5, // ldr x0, [pc - page_size - 4]
+        0xf9400810,               // ldr x16, [x0, #16]
+        0xd61f0200,               // br x16
+    ];
+    let mut code = [0; SLOT_SIZE];
+    for (bytes, instruction) in code.chunks_exact_mut(4).zip(instructions) {
+        bytes.copy_from_slice(&instruction.to_le_bytes());
+    }
+    Some(code)
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn template(_page_size: usize, _stret: bool) -> Option<[u8; SLOT_SIZE]> {
+    None
+}
+
+#[cfg(target_arch = "aarch64")]
+extern "C" {
+    fn __clear_cache(start: *mut libc::c_char, end: *mut libc::c_char);
+}
+
+#[derive(Default)]
+struct Trampolines {
+    /// Address of every executable page, mapped to whether its trampolines
+    /// are for blocks returning structures through memory.
+    pages: BTreeMap<usize, bool>,
+    /// Unused trampolines, by whether they're for structure returns.
+    free: [Vec<usize>; 2],
+}
+
+static TRAMPOLINES: LazyLock<Mutex<Trampolines>> = LazyLock::new(Default::default);
+
+impl Trampolines {
+    /// Maps a new pair of pages, adding its trampolines to the free list.
+    fn grow(&mut self, stret: bool) -> Option<()> {
+        let page_size = *PAGE_SIZE;
+        let code = template(page_size, stret)?;
+
+        let data = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                2 * page_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            return None;
+        }
+
+        let text = unsafe { data.cast::<u8>().add(page_size) };
+        for slot in 0..page_size / SLOT_SIZE {
+            unsafe {
+                text.add(slot * SLOT_SIZE)
+                    .copy_from_nonoverlapping(code.as_ptr(), SLOT_SIZE)
+            };
+        }
+        if unsafe { libc::mprotect(text.cast(), page_size, libc::PROT_READ | libc::PROT_EXEC) } != 0
+        {
+            unsafe { libc::munmap(data, 2 * page_size) };
+            return None;
+        }
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            __clear_cache(text.cast(), text.add(page_size).cast())
+        };
+
+        let text = text.expose_provenance();
+        self.pages.insert(text, stret);
+        self.free[stret as usize].extend(
+            (0..page_size / SLOT_SIZE)
+                .rev()
+                .map(|slot| text + slot * SLOT_SIZE),
+        );
+        Some(())
+    }
+
+    /// Where the block of the trampoline at [address] is kept, and whether
+    /// it's for structure returns, if [address] is one of ours.
+    fn find(&self, address: usize) -> Option<(*mut *mut Block_layout, bool)> {
+        let page_size = *PAGE_SIZE;
+        let (&text, &stret) = self.pages.range(..=address).next_back()?;
+        let offset = address - text;
+        (offset < page_size && offset.is_multiple_of(SLOT_SIZE)).then(|| {
+            let data_slot = std::ptr::with_exposed_provenance_mut(address - page_size);
+            (data_slot, stret)
+        })
+    }
+}
+
+/// Returns a trampoline that calls [block], or [None] if no more executable
+/// memory could be mapped or there are no trampolines for this architecture.
+/// The trampoline owns the reference to [block] until [remove] is called.
+pub fn allocate(block: *mut Block_layout, stret: bool) -> Option<objc_imp> {
+    let mut trampolines = TRAMPOLINES.lock().expect("poisoned mutex");
+    if trampolines.free[stret as usize].is_empty() {
+        trampolines.grow(stret)?;
+    }
+    let address = trampolines.free[stret as usize].pop()?;
+
+    let (data_slot, _) = trampolines.find(address)?;
+    unsafe { data_slot.write(block) };
+    Some(unsafe {
+        std::mem::transmute::<*const u8, objc_imp>(std::ptr::with_exposed_provenance(address))
+    })
+}
+
+/// The block called by the trampoline [imp], or [None] if [imp] isn't an
+/// allocated trampoline.
+pub fn block(imp: objc_imp) -> Option<*mut Block_layout> {
+    let trampolines = TRAMPOLINES.lock().expect("poisoned mutex");
+    let (data_slot, _) = trampolines.find(imp as usize)?;
+    let block = unsafe { data_slot.read() };
+    (!block.is_null()).then_some(block)
+}
+
+/// Frees the trampoline [imp], returning the block it called for the caller
+/// to release.
+pub fn remove(imp: objc_imp) -> Option<*mut Block_layout> {
+    let mut trampolines = TRAMPOLINES.lock().expect("poisoned mutex");
+    let address = imp as usize;
+    let (data_slot, stret) = trampolines.find(address)?;
+    let block = unsafe { data_slot.replace(std::ptr::null_mut()) };
+    if block.is_null() {
+        return None;
+    }
+    trampolines.free[stret as usize].push(address);
+    Some(block)
+}
@@ -0,0 +1,79 @@
+//! Bindings to the parts of the Itanium C++ ABI unwinder (`libgcc_s` or
+//! LLVM's `libunwind`) that exceptions are built on.
+
+use std::ffi::{c_int, c_void};
+
+pub type _Unwind_Reason_Code = c_int;
+
+pub const _URC_NO_REASON: _Unwind_Reason_Code = 0;
+pub const _URC_FOREIGN_EXCEPTION_CAUGHT: _Unwind_Reason_Code = 1;
+pub const _URC_FATAL_PHASE1_ERROR: _Unwind_Reason_Code = 3;
+pub const _URC_END_OF_STACK: _Unwind_Reason_Code = 5;
+pub const _URC_HANDLER_FOUND: _Unwind_Reason_Code = 6;
+pub const _URC_INSTALL_CONTEXT: _Unwind_Reason_Code = 7;
+pub const _URC_CONTINUE_UNWIND: _Unwind_Reason_Code = 8;
+
+pub type _Unwind_Action = c_int;
+
+pub const _UA_SEARCH_PHASE: _Unwind_Action = 1;
+pub const _UA_CLEANUP_PHASE: _Unwind_Action = 2;
+pub const _UA_HANDLER_FRAME: _Unwind_Action = 4;
+pub const _UA_FORCE_UNWIND: _Unwind_Action = 8;
+
+pub type _Unwind_Exception_Cleanup_Fn =
+    Option<extern "C" fn(_Unwind_Reason_Code, *mut _Unwind_Exception)>;
+
+/// The header every exception starts with, whatever language threw it.
+#[repr(C, align(16))]
+pub struct _Unwind_Exception {
+    /// Identifies the language and implementation that threw the exception.
+    pub exception_class: u64,
+    /// Called to destroy the exception when it is caught by a foreign
+    /// language's handler.
+    pub exception_cleanup: _Unwind_Exception_Cleanup_Fn,
+    private: [usize; 2],
+}
+
+impl _Unwind_Exception {
+    pub fn new(exception_class: u64, exception_cleanup: _Unwind_Exception_Cleanup_Fn) -> Self {
+        Self {
+            exception_class,
+            exception_cleanup,
+            private: [0; 2],
+        }
+    }
+}
+
+/// The unwinder's view of a single stack frame.
+#[repr(C)]
+pub struct _Unwind_Context {
+    _private: [u8; 0],
+}
+
+/// Registers used to pass the exception and the selected catch clause to a
+/// landing pad, as `__builtin_eh_return_data_regno(0)` and `(1)` would name
+/// them.
+#[cfg(target_arch = "x86_64")]
+pub const EH_RETURN_DATA_REGNOS: (c_int, c_int) = (0, 1);
+#[cfg(target_arch = "x86")]
+pub const EH_RETURN_DATA_REGNOS: (c_int, c_int) = (0, 2);
+#[cfg(target_arch = "aarch64")]
+pub const EH_RETURN_DATA_REGNOS: (c_int, c_int) = (0, 1);
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+pub const EH_RETURN_DATA_REGNOS: (c_int, c_int) = (10, 11);
+
+extern "C-unwind" {
+    pub fn _Unwind_RaiseException(exception: *mut _Unwind_Exception) -> _Unwind_Reason_Code;
+    pub fn _Unwind_Resume_or_Rethrow(exception: *mut _Unwind_Exception) -> _Unwind_Reason_Code;
+}
+
+extern "C" {
+    pub fn _Unwind_DeleteException(exception: *mut _Unwind_Exception);
+    pub fn _Unwind_GetLanguageSpecificData(context: *mut _Unwind_Context) -> *const c_void;
+    pub fn _Unwind_GetRegionStart(context: *mut _Unwind_Context) -> usize;
+    pub fn _Unwind_GetTextRelBase(context: *mut _Unwind_Context) -> usize;
+    pub fn _Unwind_GetDataRelBase(context: *mut _Unwind_Context) -> usize;
+    pub fn _Unwind_GetIPInfo(context: *mut _Unwind_Context, ip_before_insn: *mut c_int) -> usize;
+    pub fn _Unwind_SetGR(context: *mut _Unwind_Context, index: c_int, value: usize);
+    pub fn _Unwind_SetIP(context: *mut _Unwind_Context, value: usize);
+}
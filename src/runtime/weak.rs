@@ -0,0 +1,66 @@
+//! Zeroing weak references. Every weak variable pointing at an object is
+//! registered in the object's side table, and is set to nil when the object is
+//! destroyed. Weak loads take the same lock, so they either see the object
+//! before it starts deallocating or see nil. Small objects are never
+//! deallocated, so weak variables hold them without registering.
+
+use super::{message::id, side_table, small_object};
+
+/// Makes the weak variable at [location] point to [new], returning the value
+/// actually stored: nil if [new] is already being deallocated.
+pub fn store(location: *mut id, new: id) -> id {
+    loop {
+        let old = unsafe { location.read() };
+        let (mut old_table, mut new_table) =
+            side_table::lock_two(small_object::in_memory(old), small_object::in_memory(new));
+
+        // Another thread changed the variable before we got the locks
+        if unsafe { location.read() } != old {
+            continue;
+        }
+
+        if let Some(old) = small_object::in_memory(old) {
+            old_table
+                .as_mut()
+                .expect("locked for old value")
+                .unregister_weak(old, location);
+        }
+
+        let new = new.filter(|&new| {
+            if small_object::tag(new).is_some() {
+                return true;
+            }
+            let table = match new_table.as_mut() {
+                Some(table) => table,
+                None => old_table.as_mut().expect("locked for new value"),
+            };
+            if table.is_deallocating(new) {
+                return false;
+            }
+            table.register_weak(new, location);
+            true
+        });
+
+        unsafe { location.write(new) };
+        return new;
+    }
+}
+
+/// Loads the weak variable at [location], returning a retained reference, or
+/// nil if the object it points to is being deallocated.
+pub fn load_retained(location: *mut id) -> id {
+    loop {
+        let obj = unsafe { location.read() }?;
+        if small_object::tag(obj).is_some() {
+            return Some(obj);
+        }
+        let mut table = side_table::lock(obj);
+
+        // The variable changed before we got the lock
+        if unsafe { location.read() } != Some(obj) {
+            continue;
+        }
+
+        return table.retain(obj).then_some(obj);
+    }
+}
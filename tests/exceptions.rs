@@ -0,0 +1,208 @@
+//! Exceptions thrown through the frames in `tests/fixtures`: C++ functions,
+//! and on x86-64, stand-ins for compiled Objective-C `@try` blocks that are
+//! unwound by the runtime's personality routine.
+
+use std::{
+    ffi::{c_int, c_void, CStr},
+    os::unix::process::ExitStatusExt,
+    process::Command,
+    ptr::NonNull,
+    sync::OnceLock,
+};
+
+use objc_rs::{ffi::*, id, ClassBuilder, ClassHandle};
+
+type Body = unsafe extern "C-unwind" fn(*mut c_void);
+
+extern "C-unwind" {
+    /// Throws [value] as a C++ `int`.
+    fn fixture_cxx_throw(value: *mut c_void);
+    /// Calls [body] with [arg] under `catch (...)`. Returns 1 if it caught an
+    /// exception, or 0.
+    fn fixture_cxx_catch_all(body: Body, arg: *mut c_void) -> c_int;
+    /// Calls [body] with [arg] under `catch (int e)`. Returns 1 if it caught
+    /// an exception, which is stored in [caught], or 0.
+    fn fixture_cxx_catch_int(body: Body, arg: *mut c_void, caught: *mut c_int) -> c_int;
+    /// Calls [body] with [arg], rethrowing whatever it throws with `throw;`.
+    fn fixture_cxx_rethrow(body: Body, arg: *mut c_void);
+    fn fixture_cxx_uncaught_exceptions() -> c_int;
+}
+
+struct Classes {
+    error: ClassHandle,
+    sub_error: ClassHandle,
+    other: ClassHandle,
+}
+
+fn classes() -> &'static Classes {
+    static CLASSES: OnceLock<Classes> = OnceLock::new();
+    CLASSES.get_or_init(|| {
+        let class = |name, superclass| {
+            ClassBuilder::new(name, superclass)
+                .expect("class names are unique")
+                .register()
+        };
+        let error = class("FixtureError", None);
+        Classes {
+            error,
+            sub_error: class("FixtureSubError", Some(error)),
+            other: class("FixtureOther", None),
+        }
+    })
+}
+
+fn as_ptr(object: id) -> *mut c_void {
+    object.map_or(std::ptr::null_mut(), |object| object.as_ptr().cast())
+}
+
+extern "C-unwind" fn throw(object: *mut c_void) {
+    objc_exception_throw(NonNull::new(object.cast()))
+}
+
+extern "C-unwind" fn throw_and_rethrow_in_cxx(object: *mut c_void) {
+    unsafe { fixture_cxx_rethrow(throw, object) }
+}
+
+#[test]
+fn test_cxx_catches_objc_exception() {
+    let error = classes().error.create_instance();
+
+    // The C++ runtime destroys foreign exceptions it's done with
+    assert_eq!(unsafe { fixture_cxx_catch_all(throw, as_ptr(error)) }, 1);
+    assert_eq!(objc_retainCount(error), 1);
+
+    assert_eq!(
+        unsafe { fixture_cxx_catch_all(throw_and_rethrow_in_cxx, as_ptr(error)) },
+        1
+    );
+    assert_eq!(objc_retainCount(error), 1);
+
+    objc_release(error);
+}
+
+/// Set in the process [test_uncaught_exception_handler] starts to throw the
+/// exception, since the runtime aborts afterwards.
+const THROW_UNCAUGHT: &str = "OBJC_RS_TEST_THROW_UNCAUGHT";
+
+extern "C" fn uncaught_exception_handler(object: id) {
+    let class_name = unsafe { CStr::from_ptr(object_getClassName(object)) };
+    eprintln!("handler called with {}", class_name.to_string_lossy());
+}
+
+/// Throws from a thread of its own, since nothing may catch the exception
+/// and the test harness catches everything thrown from its threads.
+fn throw_uncaught() -> ! {
+    extern "C-unwind" fn start(object: *mut c_void) -> *mut c_void {
+        throw(object);
+        std::ptr::null_mut()
+    }
+
+    objc_setUncaughtExceptionHandler(Some(uncaught_exception_handler));
+    let error = classes().error.create_instance();
+    let mut thread = 0;
+    unsafe {
+        // Unwinding out of [start] would otherwise be stopped by Rust before
+        // the runtime gets to see that nothing catches the exception
+        let start = std::mem::transmute::<
+            extern "C-unwind" fn(*mut c_void) -> *mut c_void,
+            extern "C" fn(*mut c_void) -> *mut c_void,
+        >(start);
+        libc::pthread_create(&mut thread, std::ptr::null(), start, as_ptr(error));
+        libc::pthread_join(thread, std::ptr::null_mut());
+    }
+    unreachable!("the runtime aborts on uncaught exceptions")
+}
+
+#[test]
+fn test_uncaught_exception_handler() {
+    if std::env::var_os(THROW_UNCAUGHT).is_some() {
+        throw_uncaught();
+    }
+
+    let output = Command::new(std::env::current_exe().expect("test binary"))
+        .args(["test_uncaught_exception_handler", "--exact", "--nocapture"])
+        .env(THROW_UNCAUGHT, "1")
+        .output()
+        .expect("test binary runs");
+    assert_eq!(output.status.signal(), Some(libc::SIGABRT));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("handler called with FixtureError"));
+    assert!(stderr.contains("Terminating due to uncaught exception of class FixtureError"));
+}
+
+#[cfg(target_arch = "x86_64")]
+mod objc_frames {
+    use super::*;
+
+    extern "C-unwind" {
+        /// Calls [body] with [arg], catching `FixtureError`, then any object,
+        /// then anything at all. Returns which clause caught an exception, or
+        /// 0.
+        fn fixture_objc_catch(body: Body, arg: *mut c_void, caught: *mut *mut c_void) -> c_int;
+        /// Calls [body] with [arg], rethrowing whatever it throws.
+        fn fixture_objc_rethrow(body: Body, arg: *mut c_void);
+    }
+
+    /// Which clause of [fixture_objc_catch] catches [object] thrown by
+    /// [body], checking that it was caught as itself and destroyed
+    /// afterwards.
+    fn catch(body: Body, object: id) -> c_int {
+        let mut caught = std::ptr::null_mut();
+        let clause = unsafe { fixture_objc_catch(body, as_ptr(object), &mut caught) };
+        assert_eq!(caught, as_ptr(object));
+        if object.is_some() {
+            assert_eq!(objc_retainCount(object), 1);
+        }
+        clause
+    }
+
+    extern "C-unwind" fn throw_and_rethrow(object: *mut c_void) {
+        unsafe { fixture_objc_rethrow(throw, object) }
+    }
+
+    extern "C-unwind" fn throw_cxx_and_rethrow(value: *mut c_void) {
+        unsafe { fixture_objc_rethrow(fixture_cxx_throw, value) }
+    }
+
+    #[test]
+    fn test_catch_by_class() {
+        let classes = classes();
+        let error = classes.error.create_instance();
+        let sub_error = classes.sub_error.create_instance();
+        let other = classes.other.create_instance();
+
+        assert_eq!(catch(throw, error), 1);
+        assert_eq!(catch(throw, sub_error), 1);
+        assert_eq!(catch(throw, other), 2);
+
+        for object in [error, sub_error, other] {
+            objc_release(object);
+        }
+    }
+
+    #[test]
+    fn test_rethrow() {
+        let error = classes().error.create_instance();
+        assert_eq!(catch(throw_and_rethrow, error), 1);
+        assert_eq!(catch(throw_and_rethrow_in_cxx, error), 1);
+        objc_release(error);
+    }
+
+    #[test]
+    fn test_objc_catches_cxx_exception() {
+        // Caught by `@catch (...)` alone, as nil
+        assert_eq!(catch(fixture_cxx_throw, None), 3);
+        assert_eq!(unsafe { fixture_cxx_uncaught_exceptions() }, 0);
+
+        // Rethrown through the C++ runtime, which still knows what it holds
+        let mut caught = 0;
+        let value = std::ptr::without_provenance_mut(42);
+        assert_eq!(
+            unsafe { fixture_cxx_catch_int(throw_cxx_and_rethrow, value, &mut caught) },
+            1
+        );
+        assert_eq!(caught, 42);
+        assert_eq!(unsafe { fixture_cxx_uncaught_exceptions() }, 0);
+    }
+}
@@ -0,0 +1,49 @@
+// C++ frames for the exception tests, to check that Objective-C and C++
+// exceptions pass through each other's catch clauses.
+
+#include <cstdint>
+#include <exception>
+
+typedef void (*body_t)(void *);
+
+extern "C" {
+
+// throw (int)value;
+void fixture_cxx_throw(void *value) {
+    throw static_cast<int>(reinterpret_cast<intptr_t>(value));
+}
+
+// try { body(arg); } catch (...) { return 1; } return 0;
+int fixture_cxx_catch_all(body_t body, void *arg) {
+    try {
+        body(arg);
+    } catch (...) {
+        return 1;
+    }
+    return 0;
+}
+
+// try { body(arg); } catch (int e) { *caught = e; return 1; } return 0;
+int fixture_cxx_catch_int(body_t body, void *arg, int *caught) {
+    try {
+        body(arg);
+    } catch (int e) {
+        *caught = e;
+        return 1;
+    }
+    return 0;
+}
+
+// try { body(arg); } catch (...) { throw; }
+void fixture_cxx_rethrow(body_t body, void *arg) {
+    try {
+        body(arg);
+    } catch (...) {
+        throw;
+    }
+}
+
+int fixture_cxx_uncaught_exceptions() {
+    return std::uncaught_exceptions();
+}
+}